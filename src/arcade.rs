@@ -0,0 +1,393 @@
+//! Day 13's arcade cabinet, without a screen
+//!
+//! An `Arcade` runs the game program, decoding the (x, y, tile) triples it writes onto a
+//! `Screen`, and keeping track of the score, the ball, the paddle and how many blocks are
+//! left. The tiles themselves are drawn on a `Framebuffer`. The program draws a frame, then
+//! waits for the joystick; a `Controller` decides which way to push it from the `FrameState`.
+//!
+//! With recording enabled, the state after every frame and the joystick position chosen are
+//! kept in a `Recording`. The game is deterministic, so `Replay` can play a recording back,
+//! and the recording made while replaying should be identical.
+
+use std::fmt;
+
+use crate::grid::{Location, xy};
+use crate::intcode::{Executor, IntcodeFault, StopReason};
+use crate::intcode::devices::Framebuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tile {
+    Empty,
+    Wall,
+    Block,
+    Paddle,
+    Ball,
+}
+
+impl Tile {
+    pub fn from_id(id: i64) -> Option<Tile> {
+        use Tile::*;
+        match id {
+            0 => Some(Empty),
+            1 => Some(Wall),
+            2 => Some(Block),
+            3 => Some(Paddle),
+            4 => Some(Ball),
+            _ => None,
+        }
+    }
+
+    pub fn glyph(self) -> char {
+        use Tile::*;
+        match self {
+            Empty => ' ',
+            Wall => '#',
+            Block => 'x',
+            Paddle => '=',
+            Ball => 'o',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Joystick {
+    Left,
+    Neutral,
+    Right,
+}
+
+impl Joystick {
+    /// The input the program expects
+    pub fn value(self) -> i64 {
+        match self {
+            Joystick::Left => -1,
+            Joystick::Neutral => 0,
+            Joystick::Right => 1,
+        }
+    }
+}
+
+/// Ways the game program can misbehave
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArcadeError {
+    Fault(IntcodeFault),
+    /// A tile id that isn't one of the five known
+    BadTile { x: i64, y: i64, id: i64 },
+    /// A tile too far away for the screen
+    OffScreen { x: i64, y: i64 },
+    /// The machine stopped for something other than wanting the joystick or halting
+    UnexpectedStop(StopReason),
+}
+
+impl fmt::Display for ArcadeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArcadeError::Fault(fault) => write!(f, "{}", fault),
+            ArcadeError::BadTile{x, y, id} => write!(f, "bad tile {} at {},{}", id, x, y),
+            ArcadeError::OffScreen{x, y} => write!(f, "tile at {},{} is off the screen", x, y),
+            ArcadeError::UnexpectedStop(reason) => write!(f, "arcade stopped unexpectedly: {:?}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ArcadeError {}
+
+impl From<IntcodeFault> for ArcadeError {
+    fn from(fault: IntcodeFault) -> ArcadeError {
+        ArcadeError::Fault(fault)
+    }
+}
+
+/// The game's display. Triples with an X of -1 set the score rather than a tile.
+#[derive(Clone, Default)]
+pub struct Screen {
+    /// Tile ids, as the program wrote them
+    pixels: Framebuffer,
+    score: i64,
+    ball: Option<Location>,
+    paddle: Option<Location>,
+    blocks: usize,
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen::default()
+    }
+
+    pub fn tile(&self, loc: &Location) -> Tile {
+        self.pixels.pixel(loc.x as i64, loc.y as i64).and_then(Tile::from_id).unwrap_or(Tile::Empty)
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.pixels
+    }
+
+    /// Take an (x, y, value) triple written by the program
+    pub fn draw(&mut self, x: i64, y: i64, value: i64) -> Result<(), ArcadeError> {
+        if (x, y) == (-1, 0) {
+            self.score = value;
+            return Ok(());
+        }
+        let tile = Tile::from_id(value).ok_or(ArcadeError::BadTile{x, y, id: value})?;
+        let old = self.pixels.pixel(x, y).and_then(Tile::from_id);
+        if !self.pixels.plot(x, y, value) {
+            return Err(ArcadeError::OffScreen{x, y});
+        }
+        // On the screen, so X and Y fit
+        let loc = xy(x as i32, y as i32);
+        if old == Some(Tile::Block) {
+            self.blocks -= 1;
+        }
+        match tile {
+            Tile::Block => self.blocks += 1,
+            Tile::Ball => self.ball = Some(loc),
+            Tile::Paddle => self.paddle = Some(loc),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Draw the screen as text, a line per row
+    pub fn render(&self) -> String {
+        self.pixels.render(|id| Tile::from_id(id).map_or('?', Tile::glyph))
+    }
+}
+
+/// What's on the screen once a frame has been drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameState {
+    /// Number of frames drawn before this one
+    pub frame: usize,
+    pub score: i64,
+    pub ball: Option<Location>,
+    pub paddle: Option<Location>,
+    pub blocks: usize,
+    /// True if the game has ended
+    pub halted: bool,
+}
+
+pub trait Controller {
+    fn joystick(&mut self, state: &FrameState, screen: &Screen) -> Joystick;
+}
+
+/// Keeps the paddle under the ball
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FollowBall;
+
+impl Controller for FollowBall {
+    fn joystick(&mut self, state: &FrameState, _screen: &Screen) -> Joystick {
+        match (state.ball, state.paddle) {
+            (Some(ball), Some(paddle)) if ball.x < paddle.x => Joystick::Left,
+            (Some(ball), Some(paddle)) if ball.x > paddle.x => Joystick::Right,
+            _ => Joystick::Neutral,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedFrame {
+    pub state: FrameState,
+    /// The controller's move after seeing the frame, or None for the last frame
+    pub joystick: Option<Joystick>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl Recording {
+    pub fn joysticks(&self) -> Vec<Joystick> {
+        self.frames.iter().filter_map(|f| f.joystick).collect()
+    }
+
+    pub fn final_state(&self) -> Option<FrameState> {
+        self.frames.last().map(|f| f.state)
+    }
+}
+
+/// Plays back the moves from a recording, then leaves the joystick alone
+#[derive(Debug, Clone)]
+pub struct Replay {
+    moves: Vec<Joystick>,
+    next: usize,
+}
+
+impl Replay {
+    pub fn new(recording: &Recording) -> Replay {
+        Replay{moves: recording.joysticks(), next: 0}
+    }
+}
+
+impl Controller for Replay {
+    fn joystick(&mut self, _state: &FrameState, _screen: &Screen) -> Joystick {
+        let joystick = self.moves.get(self.next).copied().unwrap_or(Joystick::Neutral);
+        self.next += 1;
+        joystick
+    }
+}
+
+#[derive(Clone)]
+pub struct Arcade {
+    machine: Executor,
+    screen: Screen,
+    frames: usize,
+    recording: Option<Recording>,
+}
+
+impl Arcade {
+    pub fn new(program: Vec<i64>) -> Arcade {
+        Arcade{machine: Executor::new(program), screen: Screen::new(), frames: 0, recording: None}
+    }
+
+    /// Arcade set to play for free, rather than just drawing the screen and stopping
+    pub fn free_play(mut program: Vec<i64>) -> Arcade {
+        program[0] = 2;
+        Arcade::new(program)
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn machine(&self) -> &Executor {
+        &self.machine
+    }
+
+    /// For setting budgets, breakpoints and the like
+    pub fn machine_mut(&mut self) -> &mut Executor {
+        &mut self.machine
+    }
+
+    pub fn state(&self) -> FrameState {
+        let screen = &self.screen;
+        FrameState{frame: self.frames, score: screen.score, ball: screen.ball, paddle: screen.paddle,
+                   blocks: screen.blocks, halted: self.machine.halted()}
+    }
+
+    pub fn enable_recording(&mut self) {
+        self.recording = Some(Recording::default());
+    }
+
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
+    }
+
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    /// Run until the program has drawn a frame and wants the joystick, or the game is over
+    pub fn frame(&mut self) -> Result<FrameState, ArcadeError> {
+        let mut tuples = self.machine.output_tuples::<3>();
+        for [x, y, value] in tuples.by_ref() {
+            self.screen.draw(x, y, value)?;
+        }
+        match tuples.stop_reason().expect("Outputs ended without a reason")? {
+            StopReason::NeedsInput | StopReason::Halted => (),
+            reason => return Err(ArcadeError::UnexpectedStop(reason)),
+        }
+        // Everything written is on the screen now
+        self.machine.output.clear();
+        let state = self.state();
+        self.frames += 1;
+        Ok(state)
+    }
+
+    pub fn push_joystick(&mut self, joystick: Joystick) {
+        self.machine.push_input(joystick.value());
+    }
+
+    /// Play until the game is over, returning the final state
+    pub fn play<C: Controller>(&mut self, controller: &mut C) -> Result<FrameState, ArcadeError> {
+        loop {
+            let state = self.frame()?;
+            let joystick = if state.halted {
+                None
+            } else {
+                Some(controller.joystick(&state, &self.screen))
+            };
+            if let Some(recording) = &mut self.recording {
+                recording.frames.push(RecordedFrame{state, joystick});
+            }
+            match joystick {
+                Some(joystick) => self.push_joystick(joystick),
+                None => return Ok(state),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arcade::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::read_program_from_string;
+
+    fn game() -> Vec<i64> {
+        read_program_from_string(include_str!("../input/day13/input.txt").to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_attract_mode() {
+        let mut arcade = Arcade::new(game());
+        let state = arcade.play(&mut FollowBall).unwrap();
+        assert!(state.halted);
+        assert_eq!((state.frame, state.blocks, state.score), (0, 247, 0));
+        assert!(arcade.screen().render().contains("="));
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut arcade = Arcade::free_play(game());
+        arcade.enable_recording();
+        let state = arcade.play(&mut FollowBall).unwrap();
+        assert_eq!((state.blocks, state.score), (0, 12954));
+        let recording = arcade.take_recording().unwrap();
+        assert_eq!(recording.final_state(), Some(state));
+        assert_eq!(recording.frames.len(), state.frame + 1);
+
+        let mut replayed = Arcade::free_play(game());
+        replayed.enable_recording();
+        replayed.play(&mut Replay::new(&recording)).unwrap();
+        assert_eq!(replayed.take_recording().unwrap(), recording);
+    }
+
+    #[test]
+    fn test_screen() {
+        let mut screen = Screen::new();
+        // Two blocks, then the ball lands on one of them
+        for [x, y, value] in &[[1, 0, 2], [2, 0, 2], [2, 0, 4], [-1, 0, 7], [3, 1, 3]] {
+            screen.draw(*x, *y, *value).unwrap();
+        }
+        assert_eq!((screen.blocks, screen.score), (1, 7));
+        assert_eq!((screen.ball, screen.paddle), (Some(xy(2, 0)), Some(xy(3, 1))));
+        assert_eq!(screen.render(), "xo\n  =\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut arcade = Arcade::new(assemble("
+                    out   #1
+                    out   #2
+                    out   #7
+                    halt
+        ").unwrap());
+        assert_eq!(arcade.frame(), Err(ArcadeError::BadTile{x: 1, y: 2, id: 7}));
+
+        let mut arcade = Arcade::new(vec![104, 0, 99, 0]);
+        arcade.machine_mut().set_step_budget(Some(1));
+        assert_eq!(arcade.frame(), Err(ArcadeError::UnexpectedStop(StopReason::BudgetExhausted)));
+
+        let mut arcade = Arcade::new(assemble("
+                    out   #100000
+                    out   #100000
+                    out   #0
+                    halt
+        ").unwrap());
+        assert_eq!(arcade.frame(), Err(ArcadeError::OffScreen{x: 100000, y: 100000}));
+
+        let mut arcade = Arcade::new(vec![42]);
+        assert!(matches!(arcade.play(&mut FollowBall), Err(ArcadeError::Fault(IntcodeFault::UnknownOpcode{..}))));
+    }
+}
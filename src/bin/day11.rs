@@ -2,7 +2,8 @@ use structopt::StructOpt;
 use aoc2019::StandardOptions;
 use aoc2019::intcode::{Executor, read_program_from_file};
 use aoc2019::grid::{Grid, Direction, Location, xy};
-use anyhow::Result;
+use aoc2019::robot::{Pose, Robot, RobotProtocol, Stop};
+use anyhow::{Result, bail};
 
 #[derive(Clone)]
 struct GridCell {
@@ -10,50 +11,61 @@ struct GridCell {
     painted: bool,
 }
 
-fn turn(dir: Direction, turn_cmd: i64) -> Direction {
-    use Direction::*;
-    // If turn_cmd == 0 we turn left, if == 1 we turn right
-    match dir {
-        North => if turn_cmd == 0 {West} else {East},
-        East => if turn_cmd == 0 {North} else {South},
-        South => if turn_cmd == 0 {East} else {West},
-        West => if turn_cmd == 0 {South} else {North},
-    }
+/// Paints the cell it's on, turns, then moves forward
+#[derive(Clone)]
+struct Painter;
+
+struct PaintCmd {
+    white: bool,
+    turn_right: bool,
 }
 
-fn run_the_painting_robot(map: &mut Grid<GridCell>, m: &mut Executor) {
-    use Direction::*;
-    let mut p = xy(0,0); // Start robot at 0,0
-    let mut dir: Direction = North; // Start robot facing north
-    loop {
-        let cur_cell = map.get(&p).unwrap();
-        let input: Vec<i64> = if cur_cell.white {
-            vec![1]
+impl RobotProtocol for Painter {
+    type Cell = GridCell;
+    type Action = PaintCmd;
+
+    fn action_size(&self) -> usize {
+        2
+    }
+
+    fn sense(&mut self, world: &mut Grid<GridCell>, pose: &Pose) -> Vec<i64> {
+        // The camera sees 1 for white and 0 for black
+        vec![world.get(&pose.loc).unwrap().white as i64]
+    }
+
+    fn decode(&mut self, output: &[i64]) -> PaintCmd {
+        PaintCmd{white: output[0] == 1, turn_right: output[1] == 1}
+    }
+
+    fn act(&mut self, world: &mut Grid<GridCell>, pose: &mut Pose, cmd: PaintCmd) -> bool {
+        world.set(&pose.loc, Some(GridCell{white: cmd.white, painted: true}));
+        if cmd.turn_right {
+            pose.turn_right();
         } else {
-            vec![0]
-        };
-        m.set_input(input);
-        let paint_cmd = match m.run_to_output() {
-            Some(result) => result,
-            None => break,
-        };
-        let turn_cmd = match m.run_to_output() {
-            Some(result) => result,
-            None => break,
-        };
-        map.set(&p, Some(GridCell{white: paint_cmd == 1, painted: true}));
-        dir = turn(dir, turn_cmd);
-        p = p.go_one(dir);
+            pose.turn_left();
+        }
+        pose.forward();
+        true
     }
 }
 
-fn part1(program: &Vec<i64>) -> i64 {
+/// The painted grid, once the program halts. Stopping for any other reason is an error.
+fn run_the_painting_robot(map: Grid<GridCell>, m: Executor) -> Result<Grid<GridCell>> {
+    // Start robot at 0,0 facing north
+    let mut robot = Robot::new(m, Painter, Pose::new(xy(0, 0), Direction::North), map);
+    match robot.run() {
+        Stop::Halted => Ok(robot.world),
+        stop => bail!("Robot stopped after {} steps: {:?}", robot.steps(), stop),
+    }
+}
 
+fn part1(program: &Vec<i64>) -> Result<i64> {
 
-    let mut map = Grid::empty_with_default(Some(GridCell{white: false, painted: false}));
-    let mut m = Executor::new(program.clone());
+
+    let map = Grid::empty_with_default(Some(GridCell{white: false, painted: false}));
+    let m = Executor::new(program.clone());
     
-    run_the_painting_robot(&mut map, &mut m);
+    let mut map = run_the_painting_robot(map, m)?;
 
     let mut painted_count = 0;
     for (location, cell) in map.iter() {
@@ -62,16 +74,16 @@ fn part1(program: &Vec<i64>) -> i64 {
         }
     }
 
-    return painted_count;
+    Ok(painted_count)
     
 }
 
-fn part2(program: &Vec<i64>) {
+fn part2(program: &Vec<i64>) -> Result<()> {
     let mut map = Grid::empty_with_default(Some(GridCell{white: false, painted: false}));
-    let mut m = Executor::new(program.clone());
+    let m = Executor::new(program.clone());
     map.set(&xy(0, 0), Some(GridCell{white: true, painted: false}));
 
-    run_the_painting_robot(&mut map, &mut m);
+    let mut map = run_the_painting_robot(map, m)?;
 
     for y in map.top..map.top+map.height {
         let mut content = false;
@@ -87,6 +99,7 @@ fn part2(program: &Vec<i64>) {
             print!("\n");
         }
     }
+    Ok(())
 }
 
 
@@ -96,11 +109,14 @@ fn main() {
     
     let program = read_program_from_file(opt.input).unwrap();
     
-    if opt.part1 {
-        let count = part1(&program);
-        println!("Number of painted cells: {}", count);
+    let result = if opt.part1 {
+        part1(&program).map(|count| println!("Number of painted cells: {}", count))
     } else {
-        part2(&program);
+        part2(&program)
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
 }
 
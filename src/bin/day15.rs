@@ -3,59 +3,91 @@ use std::collections::HashMap;
 use structopt::StructOpt;
 use aoc2019::StandardOptions;
 use aoc2019::intcode::{Executor, read_program_from_file};
+use aoc2019::intcode::memory::MemoryKind;
 use aoc2019::grid::{Grid, Direction, Location, xy};
+use aoc2019::robot::{Pose, Robot, RobotProtocol};
 use anyhow::Result;
 
-fn try_move(m: &Executor, dir: Direction) -> Option<(Executor, bool)> {
-    use Direction::*;
+/// Status codes from the repair droid
+const WALL: i64 = 0;
+const FOUND: i64 = 2;
 
-    let mut m = m.clone();
-    let input: i64 = match dir {
-        North => 1,
-        South => 2,
-        West => 3,
-        East => 4
-    };
-    m.set_input(vec![input]);
-    let output = m.run_to_output().unwrap();
-    if output == 0 {
-        None
-    } else if output == 1 {
-        Some((m, false))
-    } else {
-        Some((m, true)) // Found our target
-    }
+/// Tries to move the way it's facing, and reports back what it ran into
+#[derive(Clone)]
+struct Droid {
+    status: i64,
 }
 
+impl RobotProtocol for Droid {
+    type Cell = ();
+    type Action = i64;
+
+    fn action_size(&self) -> usize {
+        1
+    }
+
+    fn sense(&mut self, _world: &mut Grid<()>, pose: &Pose) -> Vec<i64> {
+        use Direction::*;
+        let input = match pose.facing {
+            North => 1,
+            South => 2,
+            West => 3,
+            East => 4
+        };
+        vec![input]
+    }
+
+    fn decode(&mut self, output: &[i64]) -> i64 {
+        output[0]
+    }
 
-struct Room {
-    loc: Location,
-    m: Executor,
+    fn act(&mut self, _world: &mut Grid<()>, pose: &mut Pose, status: i64) -> bool {
+        self.status = status;
+        if status != WALL {
+            pose.forward();
+        }
+        true
+    }
 }
 
-fn part1(program: &Vec<i64>) -> (i32, Executor) {
+fn try_move(r: &Robot<Droid>, dir: Direction) -> Option<(Robot<Droid>, bool)> {
+    let mut r = r.clone();
+    r.pose.facing = dir;
+    r.step().unwrap();
+    match r.protocol.status {
+        WALL => None,
+        FOUND => Some((r, true)), // Found our target
+        _ => Some((r, false)),
+    }
+}
+
+
+fn part1(program: &Vec<i64>) -> (i32, Robot<Droid>) {
     let mut steps = 0;
-    // Store a machine state for each room we find, i.e. each location is represented by 
-    // the state of the Intcode machine when the "robot" is in that room
+    // Store a robot for each room we find, i.e. each location is represented by the state
+    // of the Intcode machine when the robot is in that room
     // Visit each new adjacent room, expanding outward until we reach the target at which
     // point we know we took the shorted path. 
-    let mut rooms: Vec<Room> = vec![Room{loc: xy(0,0), m: Executor::new(program.clone())}];
+    // The world is left empty, so that cloning the robots stays cheap
+    let m = Executor::with_memory(MemoryKind::CopyOnWrite.load(program.clone()));
+    let robot = Robot::new(m, Droid{status: 1}, Pose::new(xy(0,0), Direction::North), Grid::empty());
+    let mut rooms: Vec<Robot<Droid>> = vec![robot];
     let mut map: HashMap<Location, bool> = HashMap::new();
     loop {
-        let mut next_rooms: Vec<Room> = vec![];
+        let mut next_rooms: Vec<Robot<Droid>> = vec![];
         steps += 1;
         for r in &rooms {
             for dir in Direction::iter() {
-                let newloc = r.loc.go_one(*dir);
+                let newloc = r.pose.loc.go_one(*dir);
                 if map.contains_key(&newloc) {
                     continue;
                 }
-                match(try_move(&r.m, *dir)) {
-                    Some((m, target_reached)) => {
+                match(try_move(r, *dir)) {
+                    Some((robot, target_reached)) => {
                         if target_reached {
-                            return (steps, m);
+                            return (steps, robot);
                         }
-                        next_rooms.push(Room{loc: newloc, m});
+                        next_rooms.push(robot);
                         map.insert(newloc, true);
                     },
                     None => (),
@@ -67,24 +99,24 @@ fn part1(program: &Vec<i64>) -> (i32, Executor) {
     }
 }
 
-fn part2(m: Executor) -> i32 {
+fn part2(robot: Robot<Droid>) -> i32 {
     // Do the exact same thing as part 1, except start from the oxygen room we 
     // found in part 1, and run until we reach the farthest room
     let mut steps = 0;
-    let mut rooms: Vec<Room> = vec![Room{loc: xy(0,0), m: m}];
+    let mut rooms: Vec<Robot<Droid>> = vec![robot];
     let mut map: HashMap<Location, bool> = HashMap::new();
     loop {
-        let mut next_rooms: Vec<Room> = vec![];
+        let mut next_rooms: Vec<Robot<Droid>> = vec![];
         steps += 1;
         for r in &rooms {
             for dir in Direction::iter() {
-                let newloc = r.loc.go_one(*dir);
+                let newloc = r.pose.loc.go_one(*dir);
                 if map.contains_key(&newloc) {
                     continue;
                 }
-                match(try_move(&r.m, *dir)) {
-                    Some((m, target_reached)) => {
-                        next_rooms.push(Room{loc: newloc, m});
+                match(try_move(r, *dir)) {
+                    Some((robot, target_reached)) => {
+                        next_rooms.push(robot);
                         map.insert(newloc, true);
                     },
                     None => (),
@@ -112,8 +144,8 @@ fn main() {
         let (count, _) = part1(&program);
         println!("Distance: {}", count);
     } else {
-        let (_, oxygen_room_machine) = part1(&program);
-        let count = part2(oxygen_room_machine);
+        let (_, oxygen_room_robot) = part1(&program);
+        let count = part2(oxygen_room_robot);
         // < 329
         println!("distance: {}", count);
     }
@@ -4,7 +4,13 @@ use structopt::StructOpt;
 
 use anyhow::Result;
 use aoc2019::StandardOptions;
-use aoc2019::intcode::{execute_program, read_program_from_file};
+use aoc2019::intcode::{Executor, StepOutcome, execute_program, read_program_from_file};
+use aoc2019::intcode::batch::{Batch, Job};
+use aoc2019::intcode::taint::Source;
+use aoc2019::intcode::translate::translate;
+
+/// More than any sensible noun and verb need. Patches that take longer aren't the answer.
+const MAX_STEPS: u64 = 100_000;
 
 #[derive(Debug, StructOpt)]
 struct Options {
@@ -25,23 +31,71 @@ fn main() {
         // "before running the program, replace position 1 with the value 12 and replace position 2 with the value 2"
         program[1] = 12;
         program[2] = 2;
-        let (result, _) = execute_program(&program, &Vec::<i64>::new());
+        let (result, _) = execute_program(&program, &Vec::<i64>::new()).unwrap();
         println!("Result: {}", result[0]);
         
     } else {
         let target_result = 19690720;
-        for noun in 0..100 {
+        let translated = translate(&program);
+        let result = |noun, verb| translated.execute_patched(&[(1, noun), (2, verb)], &[], Some(MAX_STEPS)).ok().flatten().map(|(result, _)| result[0]);
+
+        // If taint tracking shows the result only depends on the noun and verb, and a straight
+        // line fits, solve for the noun rather than trying them all
+        let mut m = Executor::new(program.clone());
+        m.enable_taint(&[1, 2]);
+        m.set_step_budget(Some(MAX_STEPS));
+        let fit = match m.run() {
+            Ok(StepOutcome::Halted) => {
+                let depends_on = m.taint().unwrap().memory(0);
+                println!("Result depends on: {}", depends_on.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", "));
+                if depends_on.iter().all(|s| matches!(s, Source::Memory(1) | Source::Memory(2))) {
+                    fit_linear(result)
+                } else {
+                    None
+                }
+            },
+            Ok(_) => {
+                println!("Taint run didn't halt within {} steps", MAX_STEPS);
+                None
+            },
+            Err(fault) => {
+                println!("Taint run failed: {}", fault);
+                None
+            },
+        };
+        if let Some((base, per_noun, per_verb)) = fit {
+            println!("Result = {} + {} * noun + {} * verb", base, per_noun, per_verb);
             for verb in 0..100 {
-                program[1] = noun;
-                program[2] = verb;
-                let (result, _) = execute_program(&program, &Vec::<i64>::new());
-                if result[0] == target_result {
-                    println!("Found input noun={}, verb = {}", noun, verb);
-                    break;
+                let rest = target_result - base - per_verb * verb;
+                if per_noun != 0 && rest % per_noun == 0 && (0..100).contains(&(rest / per_noun)) {
+                    println!("Found input noun={}, verb = {}", rest / per_noun, verb);
+                    return;
                 }
             }
         }
+
+        // Some patches send the program off into the weeds, or round in circles, those just
+        // aren't the answer
+        let jobs: Vec<Job> = (0..100).flat_map(|noun| (0..100).map(move |verb| Job::patched(vec![(1, noun), (2, verb)]))).collect();
+        let batch = Batch::new(program).step_budget(Some(MAX_STEPS));
+        if let Some((i, _)) = batch.find(&jobs, |r| r.halted && r.memory[0] == target_result) {
+            println!("Found input noun={}, verb = {}", i / 100, i % 100);
+        }
+    }
+}
+
+/// Fit `f(noun, verb) = base + a * noun + b * verb` from a few runs, checking it at some
+/// more points before trusting it
+fn fit_linear<F: Fn(i64, i64) -> Option<i64>>(f: F) -> Option<(i64, i64, i64)> {
+    let base = f(0, 0)?;
+    let per_noun = f(1, 0)? - base;
+    let per_verb = f(0, 1)? - base;
+    for (noun, verb) in &[(99, 0), (0, 99), (37, 58), (99, 99)] {
+        if f(*noun, *verb)? != base + per_noun * noun + per_verb * verb {
+            return None;
+        }
     }
+    Some((base, per_noun, per_verb))
 }
 
 #[cfg(test)]
@@ -49,7 +103,7 @@ mod tests {
     use crate::*;
 
     fn test_vector(program: Vec<i64>, expected: Vec<i64>) {
-        let (output, _) = execute_program(&program, &Vec::<i64>::new());
+        let (output, _) = execute_program(&program, &Vec::<i64>::new()).unwrap();
         assert_eq!(output, expected);
     }
     #[test]
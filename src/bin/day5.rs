@@ -4,14 +4,14 @@ use aoc2019::StandardOptions;
 
 fn part1(program: Vec<i64>) -> i64 {
     let input: Vec<i64> = vec![1];
-    let (_mem, output) = execute_program(&program, &input);
+    let (_mem, output) = execute_program(&program, &input).unwrap();
     println!("Program output: {:?}",  output);
     output[output.len()-1]
 }
 
 fn part2(program: Vec<i64>) -> i64 {
     let input: Vec<i64> = vec![5];
-    let (_mem, output) = execute_program(&program, &input);
+    let (_mem, output) = execute_program(&program, &input).unwrap();
     println!("Program output: {:?}",  output);
     output[output.len()-1]
 }
@@ -58,15 +58,15 @@ mod tests {
 
         println!("--- Testing input 7");
         let input = vec![7];
-        let (_mem, output) = execute_program(&program, &input);
+        let (_mem, output) = execute_program(&program, &input).unwrap();
         assert_eq!(output[0], 999);
         println!("--- Testing input 8");
         let input = vec![8];
-        let (_mem, output) = execute_program(&program, &input);
+        let (_mem, output) = execute_program(&program, &input).unwrap();
         assert_eq!(output[0], 1000);
         println!("--- Testing input 9");
         let input = vec![9];
-        let (_mem, output) = execute_program(&program, &input);
+        let (_mem, output) = execute_program(&program, &input).unwrap();
         assert_eq!(output[0], 1001);
     }
 }
\ No newline at end of file
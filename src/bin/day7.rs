@@ -1,11 +1,25 @@
 use std::cmp::max;
 
+use anyhow::{Result, anyhow, bail};
 use structopt::StructOpt;
-use aoc2019::intcode::{Executor, execute_program, read_program_from_file};
+use aoc2019::intcode::read_program_from_file;
+use aoc2019::intcode::batch::{Batch, Job};
+use aoc2019::intcode::coverage::Coverage;
+use aoc2019::intcode::network::{Network, NetworkState};
 use aoc2019::StandardOptions;
 
 const NUM_AMP: usize = 5;
 
+#[derive(Debug, StructOpt)]
+struct Options {
+    #[structopt(flatten)]
+    shared: StandardOptions,
+
+    /// Print code coverage over all the amplifier runs in part 1
+    #[structopt(long)]
+    coverage: bool,
+}
+
 fn permutations(values: &Vec<i32>) -> Vec<Vec<i32>> {
     if values.len() == 1 {
         return vec![vec![values[0]]];
@@ -28,74 +42,61 @@ fn permutations(values: &Vec<i32>) -> Vec<Vec<i32>> {
     r
 }
 
-fn part1(program: &Vec<i64>) -> i64 {
-    let mut max_out = 0i64;
-    for phase in permutations(&vec![0, 1, 2, 3, 4]) {
-        let mut input: Vec<i64> = vec![0, 0];
-        for i in 0..NUM_AMP {
-            input[0] = phase[i] as i64;
-            let (_mem, output) = execute_program(&program, &input);
-            input[1] = output[0];
+fn part1(program: &Vec<i64>, mut coverage: Option<&mut Coverage>) -> i64 {
+    // Run every phase setting at once, an amplifier at a time
+    let phases = permutations(&vec![0, 1, 2, 3, 4]);
+    let batch = Batch::new(program.clone()).collect_coverage(coverage.is_some());
+    let mut signals = vec![0i64; phases.len()];
+    for i in 0..NUM_AMP {
+        let jobs: Vec<Job> = phases.iter().zip(&signals).map(|(phase, signal)| Job::with_input(vec![phase[i] as i64, *signal])).collect();
+        for (signal, result) in signals.iter_mut().zip(batch.run(&jobs)) {
+            let result = result.unwrap();
+            if let (Some(total), Some(run)) = (coverage.as_deref_mut(), &result.coverage) {
+                total.merge(run);
+            }
+            *signal = result.output[0];
         }
-        max_out = max(max_out, input[1]);
     }
-    return max_out;
+    signals.into_iter().max().unwrap()
 }
 
-fn part2(program: &Vec<i64>) -> i64 {
+fn part2(program: &Vec<i64>) -> Result<i64> {
     let mut max_out = 0;
     for phase in permutations(&vec![5, 6, 7, 8, 9]) {
-        let mut boxen = vec![];
-        for _ in 0..NUM_AMP {
-            boxen.push(Executor::new(program.clone()));
-        }
-
-        // First round, we input phase
-        let mut input = vec![0i64, 0];
-        let mut amp_output = 0;
-        for i in 0..NUM_AMP {
-            input[0] = phase[i] as i64;
-            boxen[i].set_input(input.clone());
-            let output = boxen[i].run_to_output().unwrap(); 
-            input[1] = output;
-        }
-
-        println!("Finished first cycle");
-
-        let mut halted = false;
-        let mut input = vec![input[1]];
-        while !halted {
-            for i in 0..NUM_AMP {
-                boxen[i].set_input(input.clone());
-                let output = boxen[i].run_to_output();
-                if output.is_none() {
-                    // Machine must have halted
-                    halted = true;
-                    break;
-                } 
-                input[0] = output.unwrap();
-                if i == NUM_AMP-1 {
-                    amp_output = input[0];
-                }
-            }
+        // Each amp gets its phase, and the first one gets the initial 0 signal
+        let mut inputs: Vec<Vec<i64>> = phase.iter().map(|p| vec![*p as i64]).collect();
+        inputs[0].push(0);
+        let result = Network::ring(program, inputs).run();
+        if result.state != NetworkState::Halted {
+            bail!("Amplifiers with phases {:?} didn't halt: {:?}", phase, result.state);
         }
+        let amp_output = *result.outputs[NUM_AMP - 1].last().ok_or_else(|| anyhow!("Last amplifier with phases {:?} gave no output", phase))?;
         max_out = max(amp_output, max_out);
     }
-    max_out
+    Ok(max_out)
 }
 
 fn main() {
-    let opt = StandardOptions::from_args();
+    let opt = Options::from_args();
     let _ = simple_logger::init();
 
-    let program = read_program_from_file(opt.input).unwrap();
+    let program = read_program_from_file(opt.shared.input).unwrap();
 
-    if opt.part1 {
-        let result = part1(&program);
+    if opt.shared.part1 {
+        let mut coverage = Coverage::default();
+        let result = part1(&program, if opt.coverage { Some(&mut coverage) } else { None });
         println!("Answer: {}", result);
+        if opt.coverage {
+            print!("{}", coverage.report(&program));
+        }
     } else {
-        let result = part2(&program);
-        println!("Answer: {}", result);
+        match part2(&program) {
+            Ok(result) => println!("Answer: {}", result),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        }
     }
 }
 
@@ -121,7 +122,7 @@ mod tests {
     fn test_part1_1() {
         let program = "3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0";
         let program = read_program_from_string(program.to_string()).unwrap();
-        let result = part1(&program);
+        let result = part1(&program, None);
         assert_eq!(result, 43210);
     }
 
@@ -130,8 +131,12 @@ mod tests {
         let program = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,\
                       27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
         let program = read_program_from_string(program.to_string()).unwrap();
-        let result = part2(&program);
+        let result = part2(&program).unwrap();
         assert_eq!(result, 139629729);
+
+        // The last amplifier never outputs, and the first waits for it forever
+        let program = read_program_from_string("3,9,3,9,1105,1,2,99,0,0".to_string()).unwrap();
+        assert!(part2(&program).unwrap_err().to_string().contains("Deadlocked"));
     }
     
 }
\ No newline at end of file
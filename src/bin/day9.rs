@@ -8,7 +8,7 @@ fn part1(program: &Vec<i64>) -> i64 {
     let input: Vec<i64> = vec![1];
     let mut m = Executor::new(program.clone());
     m.set_input(input);
-    m.run();
+    m.run().unwrap();
     return m.output[0];
 }
 
@@ -16,7 +16,7 @@ fn part2(program: &Vec<i64>) -> i64 {
     let input: Vec<i64> = vec![2];
     let mut m = Executor::new(program.clone());
     m.set_input(input);
-    m.run();
+    m.run().unwrap();
     return m.output[0];
 }
 
@@ -48,7 +48,7 @@ mod tests {
     fn test_part1_ex1() {
         let program = read_program_from_string("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99".to_string()).unwrap();
         let mut m = Executor::new(program.clone());
-        m.run();
+        m.run().unwrap();
         assert_eq!(program, m.output);
     }
 }
\ No newline at end of file
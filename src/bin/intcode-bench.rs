@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+use structopt::StructOpt;
+use aoc2019::intcode::{Executor, read_program_from_file};
+use aoc2019::intcode::translate::translate;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "intcode-bench", about = "Time an Intcode program with and without the decode cache")]
+struct Options {
+    /// Program file
+    #[structopt(short, long)]
+    input: String,
+
+    /// Comma separated input values
+    #[structopt(long = "in", default_value = "")]
+    values: String,
+
+    /// Number of times to run each way
+    #[structopt(short = "n", long, default_value = "10")]
+    iterations: u32,
+}
+
+/// Best time out of `iterations` runs of `f`
+fn time<F: FnMut() -> Vec<i64>>(iterations: u32, mut f: F) -> (Duration, Vec<i64>) {
+    let mut best = Duration::MAX;
+    let mut output = vec![];
+    for _ in 0..iterations {
+        let start = Instant::now();
+        output = f();
+        best = best.min(start.elapsed());
+    }
+    (best, output)
+}
+
+fn main() {
+    let opt = Options::from_args();
+    let _ = simple_logger::init();
+
+    let program = read_program_from_file(opt.input).unwrap();
+    let input: Vec<i64> = opt.values.split(',').filter(|v| !v.trim().is_empty()).map(|v| v.trim().parse().unwrap()).collect();
+
+    let interpret = |cached: bool| {
+        let mut m = Executor::new(program.clone());
+        m.set_decode_cache(cached);
+        m.set_input(input.clone());
+        m.run().unwrap();
+        m.output
+    };
+    let (uncached, expected) = time(opt.iterations, || interpret(false));
+    let (cached, output) = time(opt.iterations, || interpret(true));
+    assert_eq!(output, expected, "Decode cache changed the output");
+    let translated_program = translate(&program);
+    let (translated, output) = time(opt.iterations, || translated_program.execute(&input).unwrap().1);
+    assert_eq!(output, expected, "Translation changed the output");
+
+    println!("Output: {:?}", expected);
+    println!("{:<12}{:>12}{:>10}", "mode", "best", "speedup");
+    for (name, t) in &[("uncached", uncached), ("cached", cached), ("translated", translated)] {
+        println!("{:<12}{:>12?}{:>9.1}x", name, t, uncached.as_secs_f64() / t.as_secs_f64());
+    }
+}
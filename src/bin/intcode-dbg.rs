@@ -0,0 +1,43 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use structopt::StructOpt;
+use aoc2019::intcode::read_program_from_file;
+use aoc2019::intcode::debugger::Debugger;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "intcode-dbg", about = "Interactive Intcode debugger")]
+struct Options {
+    /// Program file
+    #[structopt(short, long)]
+    input: String,
+
+    /// File of debugger commands to run before going interactive
+    #[structopt(short, long)]
+    script: Option<String>,
+}
+
+fn main() {
+    let opt = Options::from_args();
+    let _ = simple_logger::init();
+
+    let program = read_program_from_file(opt.input).unwrap();
+    let mut dbg = Debugger::new(program);
+
+    if let Some(script) = opt.script {
+        let script = fs::read_to_string(script).unwrap();
+        print!("{}", dbg.run_script(&script));
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    while !dbg.quit_requested() {
+        print!("(icdb) ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(line) => line.unwrap(),
+            None => break,
+        };
+        print!("{}", dbg.command(&line));
+    }
+}
@@ -0,0 +1,35 @@
+use structopt::StructOpt;
+use aoc2019::intcode::read_program_from_file;
+use aoc2019::intcode::analysis::analyze;
+use aoc2019::intcode::disasm::disassemble;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "intcode-disasm", about = "Print an annotated listing of an Intcode program")]
+struct Options {
+    /// Program file
+    #[structopt(short, long)]
+    input: String,
+
+    /// Print the control-flow graph in Graphviz DOT format instead
+    #[structopt(long)]
+    dot: bool,
+}
+
+fn main() {
+    let opt = Options::from_args();
+    let _ = simple_logger::init();
+
+    let program = read_program_from_file(opt.input).unwrap();
+    if opt.dot {
+        let cfg = analyze(&program);
+        for jump in cfg.indirect_jumps() {
+            eprintln!("indirect jump at {}", jump);
+        }
+        for write in cfg.code_writes.iter().filter(|w| w.addr.is_some()) {
+            eprintln!("instruction at {} writes over code at {}", write.pc, write.addr.unwrap());
+        }
+        print!("{}", cfg.to_dot());
+    } else {
+        print!("{}", disassemble(&program));
+    }
+}
@@ -0,0 +1,50 @@
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use structopt::StructOpt;
+use aoc2019::intcode::fuzz::{GenConfig, fuzz};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "intcode-fuzz", about = "Check Executor against a reference interpreter on random programs.
+Exits with 1 if they disagree, after printing a shrunk reproducer.")]
+struct Options {
+    /// Seed for the first program. Defaults to the time.
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Number of programs to try
+    #[structopt(short = "n", long, default_value = "10000")]
+    runs: u64,
+
+    /// Instructions per program
+    #[structopt(long, default_value = "12")]
+    instructions: usize,
+
+    /// Steps to run each program for
+    #[structopt(long, default_value = "1000")]
+    max_steps: u64,
+}
+
+fn join(words: &[i64]) -> String {
+    words.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn main() {
+    let opt = Options::from_args();
+    let _ = simple_logger::init();
+
+    let seed = opt.seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+    let config = GenConfig{instructions: opt.instructions, max_steps: opt.max_steps, ..GenConfig::default()};
+    println!("Trying {} programs from seed {}", opt.runs, seed);
+    match fuzz(seed, opt.runs, &config) {
+        None => println!("No differences"),
+        Some(failure) => {
+            println!("Seed {} gave a difference", failure.seed);
+            println!("Original: {} with input {}", join(&failure.original.program), join(&failure.original.input));
+            println!("Minimal:  {} with input {}", join(&failure.minimal.program), join(&failure.minimal.input));
+            println!("Expected: {:?}", failure.mismatch.expected);
+            println!("Executor: {:?}", failure.mismatch.actual);
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,212 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::str::FromStr;
+
+use structopt::StructOpt;
+use aoc2019::intcode::{Executor, IntcodeFault, StopConditions, StopReason, read_program_from_file};
+use aoc2019::intcode::ascii::{encode_line, render_value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    /// Comma separated, once the program stops
+    List,
+    /// One value per line, as they are written
+    Lines,
+    Ascii,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "list" => Ok(OutputFormat::List),
+            "lines" => Ok(OutputFormat::Lines),
+            "ascii" => Ok(OutputFormat::Ascii),
+            _ => Err(format!("Output format should be list, lines or ascii, not '{}'", s)),
+        }
+    }
+}
+
+fn parse_patch(s: &str) -> Result<(usize, i64), String> {
+    let (addr, value) = s.split_once('=').ok_or_else(|| format!("Patch should be addr=value, not '{}'", s))?;
+    let addr = addr.trim().parse().map_err(|_| format!("Bad patch address '{}'", addr))?;
+    let value = value.trim().parse().map_err(|_| format!("Bad patch value '{}'", value))?;
+    Ok((addr, value))
+}
+
+/// Numbers separated by commas or spaces
+#[derive(Debug, Default)]
+struct Values(Vec<i64>);
+
+impl FromStr for Values {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Values, String> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|w| !w.is_empty())
+            .map(|w| w.parse().map_err(|_| format!("'{}' isn't a number", w)))
+            .collect::<Result<Vec<i64>, String>>()
+            .map(Values)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "intcode-run", about = "Run an Intcode program, taking any further input from the terminal.
+Exits with 1 if the program faults, and 2 if it hits the step limit.")]
+struct Options {
+    /// Program file
+    #[structopt(short, long)]
+    input: String,
+
+    /// Set memory before running, as addr=value. May be given more than once.
+    #[structopt(short, long = "patch", number_of_values = 1, parse(try_from_str = parse_patch))]
+    patches: Vec<(usize, i64)>,
+
+    /// Input values to start with, separated by commas
+    #[structopt(long = "in")]
+    values: Option<Values>,
+
+    /// Stop the program after this many instructions
+    #[structopt(long)]
+    max_steps: Option<u64>,
+
+    /// How to print output: list, lines or ascii
+    #[structopt(short, long)]
+    output: Option<OutputFormat>,
+
+    /// Talk to the program in ASCII text rather than numbers. Implies --output ascii.
+    #[structopt(long)]
+    ascii: bool,
+
+    /// File of input lines to send before reading from the terminal
+    #[structopt(short, long)]
+    script: Option<String>,
+
+    /// Write the whole session to this file
+    #[structopt(short, long)]
+    transcript: Option<String>,
+
+    /// Write a coverage report over the disassembled program to this file
+    #[structopt(long)]
+    coverage: Option<String>,
+}
+
+/// Lines from the script, then from stdin. Script lines are echoed, as if they had been typed.
+struct Lines {
+    script: Vec<String>,
+    stdin: io::Stdin,
+}
+
+impl Lines {
+    fn next(&mut self) -> Option<String> {
+        io::stdout().flush().unwrap();
+        if !self.script.is_empty() {
+            let line = self.script.remove(0);
+            println!("{}", line);
+            return Some(line);
+        }
+        let mut line = String::new();
+        match self.stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+        }
+    }
+}
+
+enum Stop {
+    Fault(IntcodeFault),
+    StepLimit,
+}
+
+fn main() {
+    let opt = Options::from_args();
+    let _ = simple_logger::init();
+
+    let program = read_program_from_file(opt.input).unwrap();
+    let script = match opt.script {
+        Some(file) => fs::read_to_string(file).unwrap().lines().map(|l| l.to_string()).collect(),
+        None => vec![],
+    };
+    let mut lines = Lines{script, stdin: io::stdin()};
+    let format = opt.output.unwrap_or(if opt.ascii { OutputFormat::Ascii } else { OutputFormat::Lines });
+
+    let mut m = Executor::new(program);
+    if opt.coverage.is_some() {
+        m.enable_coverage();
+    }
+    for (addr, value) in &opt.patches {
+        if let Err(fault) = m.poke(*addr, *value) {
+            eprintln!("bad patch: {}", fault);
+            process::exit(1);
+        }
+    }
+    // The program as it's run, for the coverage report
+    let patched = m.image().to_vec().expect("Flat memory fits in a Vec");
+    m.set_input(opt.values.unwrap_or_default().0);
+    m.set_step_budget(opt.max_steps);
+
+    let mut transcript = String::new();
+    let mut all_output = vec![];
+    let stop = loop {
+        let result = m.run_until(&StopConditions::default());
+        for x in m.output.drain(..) {
+            let text = match format {
+                OutputFormat::Ascii => render_value(x),
+                _ => format!("{}\n", x),
+            };
+            if format != OutputFormat::List {
+                print!("{}", text);
+            }
+            transcript.push_str(&text);
+            all_output.push(x.to_string());
+        }
+        match result {
+            Ok(StopReason::Halted) => break None,
+            Ok(StopReason::BudgetExhausted) => break Some(Stop::StepLimit),
+            Ok(_) => (),
+            Err(fault) => break Some(Stop::Fault(fault)),
+        }
+
+        if !opt.ascii {
+            print!("input> ");
+            transcript.push_str("input> ");
+        }
+        let line = match lines.next() {
+            Some(line) => line,
+            None => break Some(Stop::Fault(IntcodeFault::InputExhausted{pc: m.pc(), instr: m.peek(m.pc() as usize)})),
+        };
+        transcript.push_str(&line);
+        transcript.push('\n');
+        if opt.ascii {
+            encode_line(&line).into_iter().for_each(|x| m.push_input(x));
+        } else {
+            match line.parse::<Values>() {
+                Ok(values) => values.0.into_iter().for_each(|x| m.push_input(x)),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    };
+
+    if format == OutputFormat::List {
+        println!("{}", all_output.join(","));
+    }
+    if let Some(file) = opt.transcript {
+        fs::write(file, transcript).unwrap();
+    }
+    if let (Some(file), Some(coverage)) = (opt.coverage, m.coverage()) {
+        fs::write(file, coverage.report(&patched).to_string()).unwrap();
+    }
+    match stop {
+        None => (),
+        Some(Stop::Fault(fault)) => {
+            eprintln!("fault: {}", fault);
+            process::exit(1);
+        },
+        Some(Stop::StepLimit) => {
+            eprintln!("stopped after {} steps", m.steps());
+            process::exit(2);
+        },
+    }
+}
@@ -8,7 +8,7 @@ pub struct Location {
     pub y: i32,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub enum Direction {
     North,
     South,
@@ -23,6 +23,26 @@ impl Direction {
         static DIRECTIONS: [Direction;  4] = [North, South, East, West];
         DIRECTIONS.into_iter()
     }
+
+    /// Direction after turning 90 degrees anticlockwise
+    pub fn left(&self) -> Direction {
+        use Direction::*;
+        match self {
+            North => West,
+            West => South,
+            South => East,
+            East => North,
+        }
+    }
+
+    /// Direction after turning 90 degrees clockwise
+    pub fn right(&self) -> Direction {
+        self.left().opposite()
+    }
+
+    pub fn opposite(&self) -> Direction {
+        self.left().left()
+    }
 }
 
 impl Location {
@@ -83,6 +103,15 @@ impl<T: Clone> Grid<T> {
         GridIterator{x: self.left, y: self.top, grid: self}
     }
 
+    /// Value at `loc` without growing the grid, or the default if it's outside
+    pub fn peek(&self, loc: &Location) -> Option<T> {
+        if loc.x < self.left || loc.x >= self.left + self.width || loc.y < self.top || loc.y >= self.top + self.height {
+            return self.default.clone();
+        }
+        let offset = (loc.y - self.top) * self.width + (loc.x - self.left);
+        self.data[offset as usize].clone()
+    }
+
     pub fn get(&mut self, loc: &Location) -> Option<T> {
         self.check_and_grow(loc);
         // if loc.x < self.left || loc.x >= self.width + self.left || loc.y < self.top || loc.y >= self.height + self.top {
@@ -192,12 +221,15 @@ mod tests {
         assert_eq!(grid.get(&xy(2, 2)).unwrap(), (Node{a: 11}));
         assert_eq!(grid.get(&xy(4, -4)).unwrap(), (Node{a: 12}));
         assert!(grid.get(&xy(0, 0)).is_none());
+        assert_eq!(grid.peek(&xy(2, 2)).unwrap(), (Node{a: 11}));
+        assert!(grid.peek(&xy(1000, 0)).is_none());
     }
 
     #[test]
     fn test_init_grid() {
         let mut grid = Grid::<Node>::new(0, 0, 10, 10, Some(Node{a:123}));
         assert_eq!(grid.get(&xy(0, 1)).unwrap(), Node{a: 123});
+        assert_eq!(grid.peek(&xy(-5, 20)).unwrap(), Node{a: 123});
     }
 
     #[test]
@@ -207,4 +239,15 @@ mod tests {
         assert_eq!(xy(2, 3).go_one(Direction::North), xy(2, 2));
         assert_eq!(xy(2, 3).go_one(Direction::South), xy(2, 4));
     }
+
+    #[test]
+    fn test_turns() {
+        assert_eq!(Direction::North.left(), Direction::West);
+        assert_eq!(Direction::North.right(), Direction::East);
+        assert_eq!(Direction::East.opposite(), Direction::West);
+        for dir in Direction::iter() {
+            assert_eq!(dir.left().right(), *dir);
+            assert_eq!(dir.right().right(), dir.opposite());
+        }
+    }
 }
\ No newline at end of file
@@ -1,12 +1,46 @@
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::fmt;
 use std::fs;
-use std::mem::discriminant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use log::*;
 
+pub mod ascii;
+pub mod analysis;
+pub mod asm;
+pub mod batch;
+pub mod cache;
+pub mod coverage;
+pub mod debugger;
+pub mod devices;
+pub mod dialect;
+pub mod disasm;
+pub mod fuzz;
+pub mod history;
+pub mod memory;
+pub mod network;
+pub mod ports;
+pub mod rng;
+pub mod snapshot;
+pub mod taint;
+pub mod trace;
+pub mod translate;
+
+use cache::{DecodeCache, Decoded};
+use coverage::Coverage;
+use devices::{Device, DeviceMap};
+use dialect::Dialect;
+use history::History;
+use memory::{FlatMemory, Image, Memory};
+use ports::{InputSource, OutputSink};
+use taint::Taint;
+use trace::Tracer;
+
 type Program = Vec<i64>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
     Add (i64, i64, i64),
     Mult (i64, i64, i64),
@@ -18,6 +52,17 @@ pub enum Instruction {
     CmpEq (i64, i64, i64),
     SetBase (i64),
     Stop,
+    /// An opcode from a `Dialect`
+    Custom (CustomOp),
+}
+
+/// An extra opcode, with the first `arity` of `args` used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CustomOp {
+    pub opcode: i64,
+    pub mnemonic: &'static str,
+    pub args: [i64; 3],
+    pub arity: usize,
 }
 
 impl Instruction {
@@ -34,28 +79,58 @@ impl Instruction {
             Instruction::CmpEq(_, _, _) => 4,
             Instruction::SetBase(_) => 2,
             Instruction::Stop => 1,
+            Instruction::Custom(op) => 1 + op.arity,
         }
     }
 
-    pub fn run(&self, m: &mut Executor) -> u32 {
-        let mut new_pc = std::u32::MAX;
+    pub fn mnemonic(&self) -> &'static str {
         use Instruction::*;
         match self {
-            Add(a, b, out) => m.write_mem(*out as usize, a + b),
-            Mult(a, b, out) => m.write_mem(*out as usize, a * b),
+            Add(_, _, _) => "add",
+            Mult(_, _, _) => "mult",
+            Input(_) => "in",
+            Output(_) => "out",
+            JmpTrue(_, _) => "jt",
+            JmpFalse(_, _) => "jf",
+            CmpLt(_, _, _) => "lt",
+            CmpEq(_, _, _) => "eq",
+            SetBase(_) => "rbo",
+            Stop => "halt",
+            Custom(op) => op.mnemonic,
+        }
+    }
+
+    /// Resolved operand values, with output operands as the address written to
+    pub fn operands(&self) -> Vec<i64> {
+        use Instruction::*;
+        match *self {
+            Add(a, b, c) | Mult(a, b, c) | CmpLt(a, b, c) | CmpEq(a, b, c) => vec![a, b, c],
+            JmpTrue(a, b) | JmpFalse(a, b) => vec![a, b],
+            Input(a) | Output(a) | SetBase(a) => vec![a],
+            Stop => vec![],
+            Custom(op) => op.args[..op.arity].to_vec(),
+        }
+    }
+
+    pub fn run<I: InputSource, O: OutputSink>(&self, m: &mut Executor<I, O>) -> Result<u32, IntcodeFault> {
+        let mut new_pc = None;
+        use Instruction::*;
+        match self {
+            Add(a, b, out) => m.write_mem(*out, a.wrapping_add(*b))?,
+            Mult(a, b, out) => m.write_mem(*out, a.wrapping_mul(*b))?,
             Input(out) => {
-                let input = m.read_input();
-                m.write_mem(*out as usize, input);
+                let input = m.read_input()?;
+                m.write_mem(*out, input)?;
             },
             Output(a) => m.write_output(*a),
             JmpTrue(a, addr) => {
                 if *a != 0 {
-                    new_pc = *addr as u32;
+                    new_pc = Some(*addr);
                 }
             },
             JmpFalse(a, addr) => {
                 if *a == 0 {
-                    new_pc = *addr as u32;
+                    new_pc = Some(*addr);
                 }
             },
             CmpLt(a, b, out) => {
@@ -64,7 +139,7 @@ impl Instruction {
                 } else {
                     0
                 };
-                m.write_mem(*out as usize, y);
+                m.write_mem(*out, y)?;
             },
             CmpEq(a, b, out) => {
                 let y = if *a == *b {
@@ -72,203 +147,835 @@ impl Instruction {
                 } else {
                     0
                 };
-                m.write_mem(*out as usize, y);
+                m.write_mem(*out, y)?;
             },
-            SetBase(a) => m.base_reg += *a,
+            SetBase(a) => m.base_reg = m.base_reg.wrapping_add(*a),
             Stop => m.halted = true,
+            Custom(op) => {
+                let handler = m.dialect.as_ref().and_then(|d| d.handler(op.opcode));
+                let handler = handler.ok_or(IntcodeFault::UnknownOpcode{pc: m.pc, instr: m.current_instr()})?;
+                new_pc = handler(m, &op.args[..op.arity])?;
+            },
         }
-        if new_pc < std::u32::MAX {
-            new_pc
-        } else {
-            m.pc + self.len() as u32
+        match new_pc {
+            Some(addr) if addr < 0 || addr > u32::MAX as i64 => Err(m.address_fault(addr)),
+            Some(addr) => Ok(addr as u32),
+            None => Ok(m.pc + self.len() as u32),
         }
     }
 }
 
-enum ArgMode {
+/// Static description of an opcode, shared by the assembler and friends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodeInfo {
+    pub opcode: i64,
+    pub mnemonic: &'static str,
+    /// One entry per argument, true if the argument is written to
+    pub outputs: &'static [bool],
+}
+
+impl OpcodeInfo {
+    pub fn arity(&self) -> usize {
+        self.outputs.len()
+    }
+}
+
+pub const OPCODES: [OpcodeInfo; 10] = [
+    OpcodeInfo{opcode: 1, mnemonic: "add", outputs: &[false, false, true]},
+    OpcodeInfo{opcode: 2, mnemonic: "mult", outputs: &[false, false, true]},
+    OpcodeInfo{opcode: 3, mnemonic: "in", outputs: &[true]},
+    OpcodeInfo{opcode: 4, mnemonic: "out", outputs: &[false]},
+    OpcodeInfo{opcode: 5, mnemonic: "jt", outputs: &[false, false]},
+    OpcodeInfo{opcode: 6, mnemonic: "jf", outputs: &[false, false]},
+    OpcodeInfo{opcode: 7, mnemonic: "lt", outputs: &[false, false, true]},
+    OpcodeInfo{opcode: 8, mnemonic: "eq", outputs: &[false, false, true]},
+    OpcodeInfo{opcode: 9, mnemonic: "rbo", outputs: &[false]},
+    OpcodeInfo{opcode: 99, mnemonic: "halt", outputs: &[]},
+];
+
+pub fn opcode_info(opcode: i64) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|info| info.opcode == opcode)
+}
+
+/// Look up an opcode in `dialect`, or among the standard ones if there isn't a dialect
+pub(crate) fn lookup(dialect: Option<&Dialect>, opcode: i64) -> Option<OpcodeInfo> {
+    match dialect {
+        Some(dialect) => dialect.info(opcode),
+        None => opcode_info(opcode).copied(),
+    }
+}
+
+pub fn opcode_by_mnemonic(mnemonic: &str) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|info| info.mnemonic == mnemonic)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgMode {
     Absolute = 0,
     Immediate = 1,
     Relative = 2,
 }
 
-fn arg_mode(cmd: u32, arg: u32) -> ArgMode {
+fn arg_mode(cmd: i64, arg: u32) -> Option<ArgMode> {
     // The first 10^2 is for the opcode two digits
-    let digit = cmd / 10u32.pow((2 + arg) as u32) % 10;
+    let digit = cmd / 10i64.pow(2 + arg) % 10;
     match digit {
-        0 => ArgMode::Absolute,
-        1 => ArgMode::Immediate,
-        2 => ArgMode::Relative,
-        _ => panic!("Unallowed argument flag"),
+        0 => Some(ArgMode::Absolute),
+        1 => Some(ArgMode::Immediate),
+        2 => Some(ArgMode::Relative),
+        _ => None,
+    }
+}
+
+/// Reasons a word can fail to decode as an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodeError {
+    UnknownOpcode,
+    /// The mode digit for the given argument isn't 0, 1 or 2
+    BadArgMode(usize),
+    /// The given argument is written to, but is flagged as immediate
+    ImmediateOutput(usize),
+}
+
+/// An instruction as it is encoded in memory, before any operands are resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawInstruction {
+    pub opcode: i64,
+    pub modes: Vec<ArgMode>,
+    pub args: Vec<i64>,
+    /// From whichever opcodes it was decoded with, standard or a dialect's
+    info: OpcodeInfo,
+}
+
+impl RawInstruction {
+    /// Number of words taken up by the instruction
+    pub fn size(&self) -> usize {
+        1 + self.args.len()
+    }
+
+    pub fn info(&self) -> &OpcodeInfo {
+        &self.info
     }
+
+    /// Encode back into memory words
+    pub fn words(&self) -> Vec<i64> {
+        let mut cmd = self.opcode;
+        for (i, mode) in self.modes.iter().enumerate() {
+            cmd += *mode as i64 * 10i64.pow(2 + i as u32);
+        }
+        let mut words = vec![cmd];
+        words.extend(&self.args);
+        words
+    }
+}
+
+/// Decode the instruction at `pc`, fetching words from memory with `fetch`
+pub fn decode<F: FnMut(usize) -> i64>(pc: usize, fetch: F) -> Result<RawInstruction, DecodeError> {
+    decode_with(pc, fetch, |opcode| opcode_info(opcode).copied())
+}
+
+/// Decode using `lookup` to find out about opcodes, so extra ones can be understood
+pub fn decode_with<F, L>(pc: usize, mut fetch: F, lookup: L) -> Result<RawInstruction, DecodeError>
+    where F: FnMut(usize) -> i64, L: Fn(i64) -> Option<OpcodeInfo> {
+    let cmd = fetch(pc);
+    let info = if cmd < 0 { None } else { lookup(cmd % 100) };
+    let info = info.ok_or(DecodeError::UnknownOpcode)?;
+
+    let mut modes = vec![];
+    let mut args = vec![];
+    for (i, is_output) in info.outputs.iter().enumerate() {
+        let mode = arg_mode(cmd, i as u32).ok_or(DecodeError::BadArgMode(i))?;
+        if *is_output && mode == ArgMode::Immediate {
+            return Err(DecodeError::ImmediateOutput(i));
+        }
+        modes.push(mode);
+        args.push(fetch(pc + i + 1));
+    }
+    Ok(RawInstruction{opcode: info.opcode, modes, args, info})
+}
+
+/// Ways a machine can fail. Each carries the PC and raw instruction word at the time of the fault.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntcodeFault {
+    UnknownOpcode { pc: u32, instr: i64 },
+    /// Argument `arg` has a mode digit other than 0, 1 or 2
+    BadArgMode { pc: u32, instr: i64, arg: usize },
+    /// Argument `arg` is written to, but is flagged as immediate
+    ImmediateOutput { pc: u32, instr: i64, arg: usize },
+    /// Memory access or jump to an address outside of memory
+    BadAddress { pc: u32, instr: i64, addr: i64 },
+    /// Program tried to read input when none was available
+    InputExhausted { pc: u32, instr: i64 },
+    /// The device mapped at `addr` refused the value written to it
+    DeviceRefused { pc: u32, instr: i64, addr: i64 },
+}
+
+impl fmt::Display for IntcodeFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use IntcodeFault::*;
+        match self {
+            UnknownOpcode{pc, instr} => write!(f, "unknown opcode in {} @ PC={}", instr, pc),
+            BadArgMode{pc, instr, arg} => write!(f, "bad mode for argument {} in {} @ PC={}", arg, instr, pc),
+            ImmediateOutput{pc, instr, arg} => write!(f, "immediate mode output argument {} in {} @ PC={}", arg, instr, pc),
+            BadAddress{pc, instr, addr} => write!(f, "bad address {} for {} @ PC={}", addr, instr, pc),
+            InputExhausted{pc, instr} => write!(f, "out of input for {} @ PC={}", instr, pc),
+            DeviceRefused{pc, instr, addr} => write!(f, "device at {} refused write by {} @ PC={}", addr, instr, pc),
+        }
+    }
+}
+
+impl std::error::Error for IntcodeFault {}
+
+/// Result of executing a single instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepOutcome {
+    /// Instruction executed, and there is more to do
+    Running,
+    /// Instruction executed and produced an output value
+    Output(i64),
+    /// Hit an input instruction with no input available. The PC is left on the
+    /// input instruction, so the machine can be resumed once input is provided.
+    NeedsInput,
+    Halted,
+    /// The step budget or deadline ran out before the instruction at the PC was started
+    BudgetExhausted,
+}
+
+/// Why `Executor::run_until` stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    /// Waiting on an input instruction, with no input available
+    NeedsInput,
+    Output(i64),
+    Halted,
+    /// About to execute the instruction at a breakpoint
+    Breakpoint(u32),
+    /// The step budget or deadline ran out. The machine can carry on once given more.
+    BudgetExhausted,
+}
+
+/// Extra reasons for `Executor::run_until` to stop, on top of halting, needing input and
+/// running out of budget, which always stop it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StopConditions {
+    /// Stop after each output
+    pub output: bool,
+    /// Stop before executing the instruction at any of these addresses
+    pub breakpoints: BTreeSet<u32>,
+}
+
+impl StopConditions {
+    pub fn on_output() -> StopConditions {
+        StopConditions{output: true, ..Default::default()}
+    }
+
+    pub fn with_breakpoint(mut self, pc: u32) -> StopConditions {
+        self.breakpoints.insert(pc);
+        self
+    }
+}
+
+/// How often, in instructions, the clock is checked against a deadline
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
 }
 
+/// A memory access to a watched address, as made by `read_mem` or `write_mem`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    pub pc: u32,
+    pub addr: i64,
+    pub access: Access,
+    pub value: i64,
+}
+
+/// An Intcode machine, reading from `I` and writing to `O`. By default input is queued up
+/// with `set_input` or `push_input`, and output is collected in a Vec. Memory is a flat Vec
+/// unless another backend is given with `with_memory` or `with_memory_and_io`.
 #[derive(Clone)]
-pub struct Executor {
+pub struct Executor<I = VecDeque<i64>, O = Vec<i64>> {
     pc: u32,
-    mem: Vec<i64>,
+    mem: Box<dyn Memory>,
     base_reg: i64,
-    input: Vec<i64>,
-    pub output: Vec<i64>,
-    halted: bool
+    pub input: I,
+    pub output: O,
+    halted: bool,
+    watch_read: HashSet<i64>,
+    watch_write: HashSet<i64>,
+    watch_hits: Vec<WatchHit>,
+    tracer: Option<Tracer>,
+    history: Option<History>,
+    taint: Option<Taint>,
+    coverage: Option<Coverage>,
+    devices: DeviceMap,
+    cache: Option<DecodeCache>,
+    steps: u64,
+    budget: Option<u64>,
+    deadline: Option<Instant>,
+    /// Steps started since the clock was last checked against the deadline
+    since_deadline_check: u64,
+    dialect: Option<Arc<Dialect>>,
+    stopped_at_breakpoint: Option<u32>,
 }
 
 impl Executor {
     pub fn new(program: Vec<i64>) -> Executor {
-        Executor{pc: 0, mem: program, output: Vec::<i64>::new(), input: Vec::<i64>::new(), halted: false, base_reg: 0}
+        Executor::with_io(program, VecDeque::new(), Vec::new())
+    }
+
+    pub fn with_memory(mem: Box<dyn Memory>) -> Executor {
+        Executor::with_memory_and_io(mem, VecDeque::new(), Vec::new())
+    }
+}
+
+impl<O: OutputSink> Executor<VecDeque<i64>, O> {
+    pub fn set_input(&mut self, input: Vec<i64>) {
+        self.input = input.into();
+    }
+
+    pub fn push_input(&mut self, x: i64) {
+        self.input.push_back(x);
+    }
+}
+
+impl<I: InputSource, O: OutputSink> Executor<I, O> {
+    pub fn with_io(program: Vec<i64>, input: I, output: O) -> Executor<I, O> {
+        Executor::with_memory_and_io(Box::new(FlatMemory(program)), input, output)
+    }
+
+    pub fn with_memory_and_io(mem: Box<dyn Memory>, input: I, output: O) -> Executor<I, O> {
+        Executor{pc: 0, mem, output, input, halted: false, base_reg: 0,
+                 watch_read: HashSet::new(), watch_write: HashSet::new(), watch_hits: vec![], tracer: None, history: None, taint: None, coverage: None, devices: DeviceMap::default(),
+                 cache: Some(DecodeCache::default()), steps: 0, budget: None, deadline: None, since_deadline_check: 0,
+                 dialect: None, stopped_at_breakpoint: None}
     }
 
-    pub fn read_mem(&mut self, addr: usize) -> i64 {
-        // Grow memory to accomodate if needed
-        if addr >= self.mem.len() {
-            self.mem.resize(addr + 1, 0);
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+    }
+
+    pub fn base_reg(&self) -> i64 {
+        self.base_reg
+    }
+
+    /// Look at memory without triggering watchpoints
+    pub fn peek(&self, addr: usize) -> i64 {
+        self.mem.read(addr)
+    }
+
+    /// Patch memory without triggering watchpoints. Fails with a bad address if memory
+    /// can't go that high.
+    pub fn poke(&mut self, addr: usize, value: i64) -> Result<(), IntcodeFault> {
+        if addr >= self.mem.limit() {
+            return Err(self.address_fault(addr as i64));
         }
-        self.mem[addr]
+        self.store(addr, value);
+        Ok(())
     }
 
-    pub fn write_mem(&mut self, addr: usize, value: i64) {
-        // Grow memory to accomodate as needed
-        if addr >= self.mem.len() {
-            self.mem.resize(addr + 1, 0);
+    /// Write to memory, dropping any cached decoding of what was there
+    fn store(&mut self, addr: usize, value: i64) {
+        if let Some(cache) = &mut self.cache {
+            cache.invalidate(addr);
         }
-        self.mem[addr] = value;
+        self.mem.write(addr, value);
     }
 
-    pub fn set_input(&mut self, input: Vec<i64>) {
-        self.input = input;
+    /// Number of instructions executed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Allow only `budget` more instructions, or any number if None. Once they are used up,
+    /// runs stop with `StepOutcome::BudgetExhausted` until the budget is set again.
+    pub fn set_step_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+    }
+
+    /// Instructions left in the budget, if there is one
+    pub fn step_budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    /// Stop runs with `StepOutcome::BudgetExhausted` once the clock passes `deadline`. It's
+    /// checked as each `run_until` starts, then only every so many instructions, so a run may
+    /// go a little over.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Set a deadline `timeout` from now
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.deadline = Some(Instant::now() + timeout);
+    }
+
+    fn budget_exhausted(&mut self) -> bool {
+        if self.budget == Some(0) {
+            return true;
+        }
+        match self.deadline {
+            Some(deadline) => {
+                let check = self.since_deadline_check == 0;
+                self.since_deadline_check = (self.since_deadline_check + 1) % DEADLINE_CHECK_INTERVAL;
+                check && Instant::now() >= deadline
+            },
+            None => false,
+        }
+    }
+
+    /// Understand the extra opcodes in `dialect` as well as the standard ones
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = Some(Arc::new(dialect));
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
     }
 
-    pub fn read_input(&mut self) -> i64 {
-        let val = self.input.remove(0);
-        println!("Reading input value {}", val);
-        val
+    pub fn dialect(&self) -> Option<&Dialect> {
+        self.dialect.as_deref()
+    }
+
+    /// Turn the decoded instruction cache on or off. It's on by default.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache = if enabled { Some(DecodeCache::default()) } else { None };
+    }
+
+    pub fn memory(&self) -> &dyn Memory {
+        self.mem.as_ref()
+    }
+
+    /// Sparse copy of memory
+    pub fn image(&self) -> Image {
+        Image::of(self.mem.as_ref())
+    }
+
+    /// Record accesses to `addr` made by the program. Hits are collected until
+    /// `take_watch_hits` is called.
+    pub fn add_watchpoint(&mut self, addr: i64, access: Access) {
+        match access {
+            Access::Read => self.watch_read.insert(addr),
+            Access::Write => self.watch_write.insert(addr),
+        };
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: i64) {
+        self.watch_read.remove(&addr);
+        self.watch_write.remove(&addr);
+    }
+
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    /// Start profiling execution, also recording every instruction if `keep_entries` is set
+    pub fn enable_trace(&mut self, keep_entries: bool) {
+        self.tracer = Some(Tracer::new(keep_entries));
+    }
+
+    pub fn trace(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    /// Stop tracing, handing back what was collected
+    pub fn take_trace(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Start recording the last `limit` instructions, so they can be undone with `step_back`
+    pub fn enable_history(&mut self, limit: usize) {
+        self.history = Some(History::new(limit));
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Start tracking which inputs, and which of the `tagged` memory cells, values depend on
+    pub fn enable_taint(&mut self, tagged: &[usize]) {
+        self.taint = Some(Taint::new(tagged));
+    }
+
+    pub fn taint(&self) -> Option<&Taint> {
+        self.taint.as_ref()
+    }
+
+    pub fn take_taint(&mut self) -> Option<Taint> {
+        self.taint.take()
+    }
+
+    /// Send reads and writes of the addresses from `base` to `device`. Panics if they overlap
+    /// another device.
+    pub fn map_device<D: Device>(&mut self, base: usize, device: D) {
+        self.devices.map(base, Box::new(device));
+    }
+
+    /// The device of type `D` mapped at `base`
+    pub fn device<D: Device>(&self, base: usize) -> Option<&D> {
+        self.devices.get(base)
+    }
+
+    pub fn device_mut<D: Device>(&mut self, base: usize) -> Option<&mut D> {
+        self.devices.get_mut(base)
+    }
+
+    /// Start noting which instructions run and which memory cells are used
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Raw instruction word at the PC, for fault reporting
+    fn current_instr(&self) -> i64 {
+        self.mem.read(self.pc as usize)
+    }
+
+    fn address_fault(&self, addr: i64) -> IntcodeFault {
+        IntcodeFault::BadAddress{pc: self.pc, instr: self.current_instr(), addr}
+    }
+
+    pub fn read_mem(&mut self, addr: i64) -> Result<i64, IntcodeFault> {
+        if addr < 0 {
+            return Err(self.address_fault(addr));
+        }
+        let value = match self.devices.read(addr as usize, self.steps) {
+            Some(value) => value,
+            None => self.mem.read(addr as usize),
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.touch(addr);
+        }
+        if let Some(taint) = &mut self.taint {
+            taint.read(addr as usize);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.read(addr as usize);
+        }
+        if self.watch_read.contains(&addr) {
+            self.watch_hits.push(WatchHit{pc: self.pc, addr, access: Access::Read, value});
+        }
+        Ok(value)
+    }
+
+    pub fn write_mem(&mut self, addr: i64, value: i64) -> Result<(), IntcodeFault> {
+        if addr < 0 {
+            return Err(self.address_fault(addr));
+        }
+        match self.devices.write(addr as usize, value, self.steps) {
+            Some(true) => (),
+            Some(false) => return Err(IntcodeFault::DeviceRefused{pc: self.pc, instr: self.current_instr(), addr}),
+            None => {
+                if addr as usize >= self.mem.limit() {
+                    return Err(self.address_fault(addr));
+                }
+                if let Some(history) = &mut self.history {
+                    history.write(addr as usize, self.mem.read(addr as usize));
+                }
+                self.store(addr as usize, value);
+            },
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.write(addr, value);
+        }
+        if let Some(taint) = &mut self.taint {
+            taint.write(addr as usize);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.write(addr as usize);
+        }
+        if self.watch_write.contains(&addr) {
+            self.watch_hits.push(WatchHit{pc: self.pc, addr, access: Access::Write, value});
+        }
+        Ok(())
+    }
+
+    pub fn read_input(&mut self) -> Result<i64, IntcodeFault> {
+        match self.input.read() {
+            Some(x) => {
+                if let Some(history) = &mut self.history {
+                    history.input(x);
+                }
+                if let Some(taint) = &mut self.taint {
+                    taint.input();
+                }
+                Ok(x)
+            },
+            None => Err(IntcodeFault::InputExhausted{pc: self.pc, instr: self.current_instr()}),
+        }
     }
 
     pub fn write_output(&mut self, x: i64) {
-        self.output.push(x);
-        println!("Out: {}", x);
+        if let Some(history) = &mut self.history {
+            history.output();
+        }
+        if let Some(taint) = &mut self.taint {
+            taint.output();
+        }
+        self.output.write(x);
     }
 
-    pub fn load(&mut self) -> Instruction {
+    pub fn load(&mut self) -> Result<Instruction, IntcodeFault> {
         let pc = self.pc as usize;
-        let cmd = self.read_mem(pc) as u32;
-        let opcode = cmd % 100;
-        
-        let mut pos = 0u32;
-
-        let mut read_argument = |output: bool| -> i64 {
-            let arg = self.read_mem(pc + pos  as usize + 1);
-            let result = if output {
-                match arg_mode(cmd, pos) {
-                    ArgMode::Immediate => panic!("Cannot use immediate mode for output arg"),
-                    ArgMode::Absolute => arg,
-                    ArgMode::Relative => arg + self.base_reg,
+        let raw = match self.cache.as_ref().and_then(|cache| cache.get(pc)) {
+            Some(decoded) => decoded,
+            None => self.decode_at_pc()?,
+        };
+
+        let mut v = [0i64; 3];
+        for (i, ((mode, arg), is_output)) in raw.modes.iter().zip(&raw.args).zip(raw.outputs).enumerate() {
+            if let Some(taint) = &mut self.taint {
+                // What's read depends on the argument word, and for relative mode the base
+                if !is_output {
+                    taint.read(pc + 1 + i);
+                    if *mode == ArgMode::Relative {
+                        taint.read_base();
+                    }
+                }
+            }
+            let value = if *is_output {
+                match mode {
+                    ArgMode::Relative => arg.wrapping_add(self.base_reg),
+                    _ => *arg,
                 }
             } else {
-                match arg_mode(cmd, pos) {
-                    ArgMode::Immediate => arg,
-                    ArgMode::Absolute => self.read_mem(arg as usize),
-                    ArgMode::Relative => self.read_mem((arg + self.base_reg) as usize),
+                match mode {
+                    ArgMode::Immediate => *arg,
+                    ArgMode::Absolute => self.read_mem(*arg)?,
+                    ArgMode::Relative => self.read_mem(arg.wrapping_add(self.base_reg))?,
                 }
             };
-            pos += 1;
-            result
-        };
+            v[i] = value;
+        }
 
         use Instruction::*;
-        let instr = match opcode {
-            1 => Add(read_argument(false), read_argument(false), read_argument(true)),
-            2 => Mult(read_argument(false), read_argument(false), read_argument(true)),
-            3 => Input(read_argument(true)),
-            4 => Output(read_argument(false)),
-            5 => JmpTrue(read_argument(false), read_argument(false)),
-            6 => JmpFalse(read_argument(false), read_argument(false)),
-            7 => CmpLt(read_argument(false), read_argument(false), read_argument(true)),
-            8 => CmpEq(read_argument(false), read_argument(false), read_argument(true)),
-            9 => SetBase(read_argument(false)),
-            99 => Stop{},
-            _ => {
-              self.dump(format!("Unrecognized instruction: {} @ PC={}", opcode, pc));
-              panic!("Aborting");  
+        Ok(match raw.opcode {
+            1 => Add(v[0], v[1], v[2]),
+            2 => Mult(v[0], v[1], v[2]),
+            3 => Input(v[0]),
+            4 => Output(v[0]),
+            5 => JmpTrue(v[0], v[1]),
+            6 => JmpFalse(v[0], v[1]),
+            7 => CmpLt(v[0], v[1], v[2]),
+            8 => CmpEq(v[0], v[1], v[2]),
+            9 => SetBase(v[0]),
+            99 => Stop,
+            opcode => Custom(CustomOp{opcode, mnemonic: lookup(self.dialect.as_deref(), opcode).map_or("?", |info| info.mnemonic), args: v, arity: raw.outputs.len()}),
+        })
+    }
+
+    fn decode_at_pc(&mut self) -> Result<Decoded, IntcodeFault> {
+        let pc = self.pc as usize;
+        let (mem, dialect) = (&self.mem, self.dialect.as_deref());
+        let decoded = match decode_with(pc, |addr| mem.read(addr), |opcode| lookup(dialect, opcode)) {
+            Ok(raw) => Decoded::new(&raw, lookup(dialect, raw.opcode).unwrap().outputs),
+            Err(e) => {
+                let (pc, instr) = (self.pc, self.current_instr());
+                return Err(match e {
+                    DecodeError::UnknownOpcode => IntcodeFault::UnknownOpcode{pc, instr},
+                    DecodeError::BadArgMode(arg) => IntcodeFault::BadArgMode{pc, instr, arg},
+                    DecodeError::ImmediateOutput(arg) => IntcodeFault::ImmediateOutput{pc, instr, arg},
+                });
             },
         };
-        //println!("{}: {:?}", pc, instr);
-        instr
+        if let Some(cache) = &mut self.cache {
+            cache.insert(pc, decoded);
+        }
+        Ok(decoded)
     }
 
-    pub fn execute(&mut self, i: &Instruction) {
-        self.pc = i.run(self);
+    pub fn execute(&mut self, i: &Instruction) -> Result<(), IntcodeFault> {
+        self.pc = i.run(self)?;
+        Ok(())
     }
 
-    /// Run program until it halts
-    pub fn run(&mut self) {
-        loop {
-            let instruction = self.load();
-            self.execute(&instruction);
-            if self.halted {
-                break;
-            }
-        }
+    pub fn halted(&self) -> bool {
+        self.halted
     }
 
-    /// Run program until an unsatisfied input instruction is reached
-    /// i.e. The input queue is empty and an input instruction is hit
-    /// Returns true if broken on input, false if machine halted
-    pub fn run_to_input(&mut self) -> bool {
-        use Instruction::*;
-        loop {
-            let instruction = self.load();
-            if discriminant(&instruction) == discriminant(&Input(0)) && self.input.len() == 0 {
-                return true;
+    /// Execute a single instruction
+    pub fn step(&mut self) -> Result<StepOutcome, IntcodeFault> {
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
+        if self.budget_exhausted() {
+            return Ok(StepOutcome::BudgetExhausted);
+        }
+        let pc = self.pc;
+        if let Some(taint) = &mut self.taint {
+            taint.begin();
+        }
+        let instruction = self.load()?;
+        if let Some(history) = &mut self.history {
+            history.begin(pc, self.base_reg, self.halted, self.mem.extent());
+        }
+        let result = self.execute(&instruction);
+        if let Some(tracer) = &mut self.tracer {
+            match result {
+                Ok(()) => tracer.record(pc, instruction, self.pc),
+                Err(_) => tracer.abandon(),
             }
-            self.execute(&instruction);
-            if self.halted {
-                return false;
+        }
+        if let Some(history) = &mut self.history {
+            match result {
+                Ok(()) => history.commit(),
+                Err(_) => history.abandon(),
             }
         }
+        if let (Some(taint), Instruction::SetBase(_), Ok(())) = (&mut self.taint, &instruction, &result) {
+            taint.rebase();
+        }
+        if let (Some(coverage), Ok(())) = (&mut self.coverage, &result) {
+            coverage.execute(pc as usize);
+        }
+        match result {
+            // Nothing has changed when input runs dry, so we can pick up from here later
+            Err(IntcodeFault::InputExhausted{..}) => return Ok(StepOutcome::NeedsInput),
+            result => result?,
+        }
+        self.steps += 1;
+        if let Some(budget) = &mut self.budget {
+            *budget -= 1;
+        }
+        Ok(match instruction {
+            Instruction::Output(x) => StepOutcome::Output(x),
+            _ if self.halted => StepOutcome::Halted,
+            _ => StepOutcome::Running,
+        })
     }
 
-    pub fn run_to_output(&mut self) -> Option<i64> {
+    /// Run program until it halts, or the budget runs out. Running out of input is a fault.
+    pub fn run(&mut self) -> Result<StepOutcome, IntcodeFault> {
+        match self.run_until(&StopConditions::default())? {
+            StopReason::NeedsInput => Err(IntcodeFault::InputExhausted{pc: self.pc, instr: self.current_instr()}),
+            StopReason::BudgetExhausted => Ok(StepOutcome::BudgetExhausted),
+            _ => Ok(StepOutcome::Halted),
+        }
+    }
+
+    /// Run until the program halts, needs input, runs out of budget, or meets one of the
+    /// extra conditions in `stop`. After stopping at a breakpoint, running again carries on
+    /// past it.
+    pub fn run_until(&mut self, stop: &StopConditions) -> Result<StopReason, IntcodeFault> {
+        self.since_deadline_check = 0;
+        let mut resuming = self.stopped_at_breakpoint.take() == Some(self.pc);
         loop {
-            let instruction = self.load();
-            self.execute(&instruction);
-            if discriminant(&instruction) == discriminant(&Instruction::Output(0)) {
-                return Some(*self.output.last().unwrap());
+            if !resuming && stop.breakpoints.contains(&self.pc) {
+                self.stopped_at_breakpoint = Some(self.pc);
+                return Ok(StopReason::Breakpoint(self.pc));
             }
-            if self.halted {
-                return None;
+            resuming = false;
+            match self.step()? {
+                StepOutcome::Running => (),
+                StepOutcome::Output(x) if stop.output => return Ok(StopReason::Output(x)),
+                StepOutcome::Output(_) => (),
+                StepOutcome::NeedsInput => return Ok(StopReason::NeedsInput),
+                StepOutcome::Halted => return Ok(StopReason::Halted),
+                StepOutcome::BudgetExhausted => return Ok(StopReason::BudgetExhausted),
             }
         }
     }
 
-    pub fn run_to_output_ntimes(&mut self, n: i32) -> Vec<i64> {
-        let mut result: Vec<i64> = Vec::new();
-        for _ in 0..n {
-            let output = self.run_to_output();
-            if output.is_none() {
-                return result;
-            }
-            result.push(output.unwrap());
-        }
-        result
+    /// Output values, produced lazily by running the machine. Ends when the machine stops for
+    /// any other reason, which can then be had from `Outputs::stop_reason`.
+    pub fn outputs(&mut self) -> Outputs<'_, I, O> {
+        Outputs{machine: self, conditions: StopConditions::on_output(), stop: None}
+    }
+
+    /// Outputs grouped into arrays of `N`, for programs that write records such as (x, y, tile).
+    /// If the machine stops part way through a record, the partial record is dropped.
+    pub fn output_tuples<const N: usize>(&mut self) -> OutputTuples<'_, I, O, N> {
+        OutputTuples{outputs: self.outputs()}
     }
 
     pub fn dump(&self, msg: String) {
-        debug!("Performing memory dump of {} words", self.mem.len());
+        let segments = self.mem.segments();
+        debug!("Performing memory dump of {} segments", segments.len());
         let mut msg: String = msg;
         msg += "\nMemory Dump: \n";
-        let mut addr = 0;
-        while addr < self.mem.len() {
-            if addr % 10 == 0 {
-                msg.push_str(&format!("\n{}: ", addr)); 
+        for (start, values) in segments {
+            for (addr, x) in (start..).zip(values) {
+                if addr == start || addr % 10 == 0 {
+                    msg.push_str(&format!("\n{}: ", addr)); 
+                }
+                msg.push_str(&format!(" {}", x));
             }
-            msg.push_str(&format!(" {}", self.mem[addr]));
-            addr += 1;
-            debug!("Printing addr {}, {}", addr, msg.len());
         }
         println!("{}", msg);
     }
 }
 
+/// Iterator returned by `Executor::outputs`
+pub struct Outputs<'a, I, O> {
+    machine: &'a mut Executor<I, O>,
+    conditions: StopConditions,
+    stop: Option<Result<StopReason, IntcodeFault>>,
+}
+
+impl<'a, I: InputSource, O: OutputSink> Outputs<'a, I, O> {
+    /// Why the outputs ended, once they have
+    pub fn stop_reason(&self) -> Option<Result<StopReason, IntcodeFault>> {
+        self.stop
+    }
+}
+
+impl<'a, I: InputSource, O: OutputSink> Iterator for Outputs<'a, I, O> {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        if self.stop.is_some() {
+            return None;
+        }
+        match self.machine.run_until(&self.conditions) {
+            Ok(StopReason::Output(x)) => Some(x),
+            other => {
+                self.stop = Some(other);
+                None
+            },
+        }
+    }
+}
+
+/// Iterator returned by `Executor::output_tuples`
+pub struct OutputTuples<'a, I, O, const N: usize> {
+    outputs: Outputs<'a, I, O>,
+}
+
+impl<'a, I: InputSource, O: OutputSink, const N: usize> OutputTuples<'a, I, O, N> {
+    pub fn stop_reason(&self) -> Option<Result<StopReason, IntcodeFault>> {
+        self.outputs.stop_reason()
+    }
+}
+
+impl<'a, I: InputSource, O: OutputSink, const N: usize> Iterator for OutputTuples<'a, I, O, N> {
+    type Item = [i64; N];
+
+    fn next(&mut self) -> Option<[i64; N]> {
+        let mut tuple = [0; N];
+        for x in &mut tuple {
+            *x = self.outputs.next()?;
+        }
+        Some(tuple)
+    }
+}
+
 pub fn read_program_from_string(s: String) -> Result<Vec<i64>> {
     let ints: Vec<i64> = s.trim().split(",").map(|s| s.parse::<i64>().unwrap()).collect();
     Ok(ints)
@@ -279,15 +986,34 @@ pub fn read_program_from_file(file: String) -> Result<Vec<i64>> {
     read_program_from_string(content)
 }
 
-pub fn execute_program(program: &Vec<i64>, input: &Vec<i64>) -> (Vec<i64>, Vec<i64>) {
+pub fn execute_program(program: &Vec<i64>, input: &Vec<i64>) -> Result<(Vec<i64>, Vec<i64>), IntcodeFault> {
     let mut exec = Executor::new(program.clone());
     exec.set_input(input.clone());
-    while !exec.halted {
-        let i = exec.load();
-        exec.execute(&i);
+    exec.run()?;
+    Ok((exec.mem.into_vec().expect("Executor::new gives flat memory"), exec.output))
+}
+
+/// Programs shared by the tests of several modules
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use crate::intcode::asm::assemble;
+    use crate::intcode::read_program_from_string;
+
+    /// Reads a count, then outputs it and counts down to zero. The count is at address 12.
+    pub fn countdown() -> Vec<i64> {
+        assemble("
+                    in    count
+            loop:   out   count
+                    add   count, #-1, count
+                    jt    count, #loop
+                    halt
+            count:  data  0
+        ").unwrap()
+    }
+
+    pub fn day9() -> Vec<i64> {
+        read_program_from_string(include_str!("../input/day9/input.txt").to_string()).unwrap()
     }
-    
-    (exec.mem, exec.output)
 }
 
 #[cfg(test)]
@@ -299,5 +1025,102 @@ mod tests {
         assert_eq!(inst.len(), 2);
     }
 
-    
+    #[test]
+    fn test_faults() {
+        let mut m = Executor::new(vec![1, 0, 0, 0, 42]);
+        assert_eq!(m.run(), Err(IntcodeFault::UnknownOpcode{pc: 4, instr: 42}));
+
+        let mut m = Executor::new(vec![301, 0, 0, 0]);
+        assert_eq!(m.run(), Err(IntcodeFault::BadArgMode{pc: 0, instr: 301, arg: 0}));
+
+        let mut m = Executor::new(vec![11101, 1, 1, 0]);
+        assert_eq!(m.step(), Err(IntcodeFault::ImmediateOutput{pc: 0, instr: 11101, arg: 2}));
+
+        let mut m = Executor::new(vec![109, -10, 204, 3, 99]);
+        assert_eq!(m.run(), Err(IntcodeFault::BadAddress{pc: 2, instr: 204, addr: -7}));
+
+        let mut m = Executor::new(vec![1105, 1, -1]);
+        assert_eq!(m.run(), Err(IntcodeFault::BadAddress{pc: 0, instr: 1105, addr: -1}));
+
+        let mut m = Executor::new(vec![3, 0, 99]);
+        assert_eq!(m.run(), Err(IntcodeFault::InputExhausted{pc: 0, instr: 3}));
+
+        // Too high for flat memory, but fine for paged
+        let huge = vec![1101, 1, 1, 1 << 40, 99];
+        let mut m = Executor::new(huge.clone());
+        assert_eq!(m.run(), Err(IntcodeFault::BadAddress{pc: 0, instr: 1101, addr: 1 << 40}));
+        assert_eq!(m.poke(1 << 40, 1), Err(IntcodeFault::BadAddress{pc: 0, instr: 1101, addr: 1 << 40}));
+        let mut m = Executor::with_memory(memory::MemoryKind::Paged.load(huge));
+        assert_eq!(m.run(), Ok(StepOutcome::Halted));
+        assert_eq!(m.peek(1 << 40), 2);
+    }
+
+    #[test]
+    fn test_resume_after_input() {
+        let mut m = Executor::new(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]);
+        assert_eq!(m.run_until(&StopConditions::default()), Ok(StopReason::NeedsInput));
+        assert_eq!(m.step(), Ok(StepOutcome::NeedsInput));
+        m.set_input(vec![41]);
+        assert_eq!(m.outputs().next(), Some(42));
+        assert_eq!(m.step(), Ok(StepOutcome::Halted));
+        assert!(m.halted());
+    }
+
+    #[test]
+    fn test_budget() {
+        // Counts up at address 7 forever
+        let program = vec![1001, 7, 1, 7, 1105, 1, 0, 0];
+        let mut m = Executor::new(program.clone());
+        m.set_step_budget(Some(10));
+        assert_eq!(m.run(), Ok(StepOutcome::BudgetExhausted));
+        assert_eq!((m.steps(), m.pc(), m.peek(7)), (10, 0, 5));
+        let mut outputs = m.outputs();
+        assert_eq!(outputs.next(), None);
+        assert_eq!(outputs.stop_reason(), Some(Ok(StopReason::BudgetExhausted)));
+
+        m.set_step_budget(Some(3));
+        assert_eq!(m.run_until(&StopConditions::on_output()), Ok(StopReason::BudgetExhausted));
+        assert_eq!((m.steps(), m.pc(), m.peek(7)), (13, 4, 7));
+
+        let mut m = Executor::new(program);
+        m.set_timeout(Duration::from_millis(20));
+        assert_eq!(m.run(), Ok(StepOutcome::BudgetExhausted));
+        assert!(m.steps().is_multiple_of(DEADLINE_CHECK_INTERVAL));
+
+        // A deadline is checked as soon as a run starts, wherever the step count has got to,
+        // even if the run would stop for output before the next regular check
+        let mut m = Executor::new(vec![104, 1, 1105, 1, 0]);
+        m.set_step_budget(Some(5));
+        m.run().unwrap();
+        m.set_step_budget(None);
+        m.set_deadline(Some(Instant::now()));
+        assert_eq!(m.outputs().count(), 0);
+        assert_eq!(m.steps(), 5);
+    }
+
+    #[test]
+    fn test_run_until() {
+        // Writes (n, 2n) for n from 3 down to 1
+        let program = asm::assemble("
+            loop:   out   n
+                    mult  n, #2, t
+                    out   t
+                    add   n, #-1, n
+                    jt    n, #loop
+                    halt
+            n:      data  3
+            t:      data  0
+        ").unwrap();
+        let mut m = Executor::new(program.clone());
+        let stop = StopConditions::on_output().with_breakpoint(2);
+        assert_eq!(m.run_until(&stop), Ok(StopReason::Output(3)));
+        assert_eq!(m.run_until(&stop), Ok(StopReason::Breakpoint(2)));
+        assert_eq!(m.run_until(&stop), Ok(StopReason::Output(6)));
+        assert_eq!(m.run_until(&StopConditions::default().with_breakpoint(2)), Ok(StopReason::Breakpoint(2)));
+
+        let mut m = Executor::new(program);
+        let mut tuples = m.output_tuples::<2>();
+        assert_eq!(tuples.by_ref().collect::<Vec<_>>(), vec![[3, 6], [2, 4], [1, 2]]);
+        assert_eq!(tuples.stop_reason(), Some(Ok(StopReason::Halted)));
+    }
 }
\ No newline at end of file
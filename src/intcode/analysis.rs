@@ -0,0 +1,265 @@
+//! Static analysis of Intcode programs
+//!
+//! `analyze` decodes the code reachable from address 0 (as found by the disassembler), splits
+//! it into basic blocks and joins them up into a control-flow graph. Two things can't be
+//! followed statically, so are reported instead:
+//!
+//! * indirect jumps, whose target is read from memory rather than given as an immediate
+//! * writes that land, or might land, on code, which make the graph untrustworthy from then on
+//!
+//! The graph can be written out in Graphviz DOT format with `Cfg::to_dot`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use crate::intcode::{ArgMode, RawInstruction};
+use crate::intcode::dialect::Dialect;
+use crate::intcode::disasm::{format_instruction, jump_target, reachable_in};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// On to the following instruction
+    Next,
+    /// A jump being taken
+    Jump,
+}
+
+/// A run of instructions which is only entered at the top and only left at the bottom
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<(usize, RawInstruction)>,
+    /// Blocks that control can pass to, by their start address
+    pub successors: Vec<(usize, EdgeKind)>,
+    /// Ends in a jump whose target isn't known until run time
+    pub indirect: bool,
+}
+
+impl Block {
+    /// Address just after the last instruction
+    pub fn end(&self) -> usize {
+        self.instructions.last().map_or(self.start, |(pc, raw)| pc + raw.size())
+    }
+
+    fn last(&self) -> &RawInstruction {
+        &self.instructions.last().unwrap().1
+    }
+}
+
+/// An instruction which writes to memory that may hold code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodeWrite {
+    pub pc: usize,
+    /// Which argument is written
+    pub arg: usize,
+    /// The address written, or `None` for relative mode writes, which could go anywhere
+    pub addr: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+    pub code_writes: Vec<CodeWrite>,
+}
+
+fn is_jump(raw: &RawInstruction) -> bool {
+    raw.opcode == 5 || raw.opcode == 6
+}
+
+/// Whether a jump's condition is fixed, and if so whether it always jumps
+fn always_jumps(raw: &RawInstruction) -> Option<bool> {
+    match raw.modes[0] {
+        ArgMode::Immediate => Some((raw.args[0] != 0) == (raw.opcode == 5)),
+        _ => None,
+    }
+}
+
+/// Build the control-flow graph of a program image
+pub fn analyze(program: &[i64]) -> Cfg {
+    analyze_in(program, None)
+}
+
+/// Build the control-flow graph of a program written with the extra opcodes in `dialect`.
+/// Control is assumed to pass straight through them.
+pub fn analyze_with(program: &[i64], dialect: &Dialect) -> Cfg {
+    analyze_in(program, Some(dialect))
+}
+
+fn analyze_in(program: &[i64], dialect: Option<&Dialect>) -> Cfg {
+    let code = reachable_in(program, &[0], dialect);
+
+    // Blocks start at the entry point, at jump targets, after jumps and halts, and wherever
+    // there's a gap in the code
+    let mut leaders: BTreeSet<usize> = BTreeSet::new();
+    leaders.insert(0);
+    let mut expected = None;
+    for (pc, raw) in &code {
+        if expected != Some(*pc) {
+            leaders.insert(*pc);
+        }
+        if is_jump(raw) || raw.opcode == 99 {
+            leaders.insert(pc + raw.size());
+        }
+        leaders.extend(jump_target(raw));
+        expected = Some(pc + raw.size());
+    }
+
+    let mut blocks: BTreeMap<usize, Block> = BTreeMap::new();
+    for (pc, raw) in &code {
+        let instruction = (*pc, raw.clone());
+        match blocks.values_mut().next_back() {
+            Some(block) if !leaders.contains(pc) => block.instructions.push(instruction),
+            _ => {
+                blocks.insert(*pc, Block{start: *pc, instructions: vec![instruction], successors: vec![], indirect: false});
+            },
+        }
+    }
+
+    let starts: BTreeSet<usize> = blocks.keys().copied().collect();
+    for block in blocks.values_mut() {
+        let last = block.last().clone();
+        let falls_through = match last.opcode {
+            99 => false,
+            5 | 6 => always_jumps(&last) != Some(true),
+            _ => true,
+        };
+        if falls_through && starts.contains(&block.end()) {
+            block.successors.push((block.end(), EdgeKind::Next));
+        }
+        if is_jump(&last) && always_jumps(&last) != Some(false) {
+            match jump_target(&last) {
+                Some(target) if starts.contains(&target) => block.successors.push((target, EdgeKind::Jump)),
+                Some(_) => (),
+                None => block.indirect = true,
+            }
+        }
+    }
+
+    let mut in_code = vec![false; program.len()];
+    for (pc, raw) in &code {
+        for c in &mut in_code[*pc..pc + raw.size()] {
+            *c = true;
+        }
+    }
+    let mut code_writes = vec![];
+    for (pc, raw) in &code {
+        for (arg, is_output) in raw.info().outputs.iter().enumerate() {
+            if !is_output {
+                continue;
+            }
+            let addr = match raw.modes[arg] {
+                ArgMode::Relative => None,
+                _ => match usize::try_from(raw.args[arg]) {
+                    Ok(addr) if addr < in_code.len() && in_code[addr] => Some(addr),
+                    _ => continue,
+                },
+            };
+            code_writes.push(CodeWrite{pc: *pc, arg, addr});
+        }
+    }
+
+    Cfg{blocks, code_writes}
+}
+
+impl Cfg {
+    /// Addresses of jumps with targets that aren't known statically
+    pub fn indirect_jumps(&self) -> Vec<usize> {
+        self.blocks.values().filter(|b| b.indirect).map(|b| b.instructions.last().unwrap().0).collect()
+    }
+
+    /// Start of the block holding the instruction at `pc`
+    pub fn block_containing(&self, pc: usize) -> Option<usize> {
+        self.blocks.range(..=pc).next_back().filter(|(_, b)| pc < b.end()).map(|(start, _)| *start)
+    }
+
+    /// Graphviz rendering. Blocks ending in an indirect jump get a dashed edge to a `?` node,
+    /// and blocks which definitely write over code are shaded.
+    pub fn to_dot(&self) -> String {
+        let modifying: BTreeSet<usize> = self.code_writes.iter()
+            .filter(|w| w.addr.is_some())
+            .filter_map(|w| self.block_containing(w.pc))
+            .collect();
+
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=monospace];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (pc, raw) in &block.instructions {
+                write!(label, "{:>5}: {}\\l", pc, format_instruction(raw)).unwrap();
+            }
+            let style = if modifying.contains(&block.start) { ", style=filled, fillcolor=salmon" } else { "" };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, style).unwrap();
+        }
+        for block in self.blocks.values() {
+            for (to, kind) in &block.successors {
+                let style = match kind {
+                    EdgeKind::Next => "",
+                    EdgeKind::Jump => " [color=blue]",
+                };
+                writeln!(dot, "    b{} -> b{}{};", block.start, to, style).unwrap();
+            }
+            if block.indirect {
+                writeln!(dot, "    b{}_indirect [label=\"?\", shape=circle];", block.start).unwrap();
+                writeln!(dot, "    b{} -> b{}_indirect [style=dashed];", block.start, block.start).unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::analysis::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::fixtures::day9;
+
+    #[test]
+    fn test_blocks() {
+        let program = assemble("
+                    in    count
+            loop:   out   count
+                    add   count, #-1, count
+                    jt    count, #loop
+                    rbo   #ret
+                    jt    #1, #sub
+                    halt
+            sub:    add   #0, #9, patch+1
+            patch:  out   #0
+                    jt    #1, rel[0]
+            ret:    data  16
+            count:  data  0
+        ").unwrap();
+        let cfg = analyze(&program);
+        assert_eq!(cfg.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 2, 11, 16, 17]);
+        assert_eq!(cfg.blocks[&0].successors, vec![(2, EdgeKind::Next)]);
+        assert_eq!(cfg.blocks[&2].successors, vec![(11, EdgeKind::Next), (2, EdgeKind::Jump)]);
+        // Always jumps, so doesn't fall through to the halt
+        assert_eq!(cfg.blocks[&11].successors, vec![(17, EdgeKind::Jump)]);
+        assert!(cfg.blocks[&16].successors.is_empty());
+        assert_eq!(cfg.indirect_jumps(), vec![23]);
+        assert_eq!(cfg.code_writes, vec![CodeWrite{pc: 17, arg: 2, addr: Some(22)}]);
+
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("b2 -> b2 [color=blue];"));
+        assert!(dot.contains("b17 -> b17_indirect [style=dashed];"));
+        assert!(dot.contains("b17 [label=\"   17: add   #0, #9, 22\\l   21: out   #0\\l   23: jt    #1, rel[0]\\l\", style=filled"));
+    }
+
+    #[test]
+    fn test_day9() {
+        let program = day9();
+        let cfg = analyze(&program);
+        // Every reachable instruction lands in exactly one block
+        let instructions: usize = cfg.blocks.values().map(|b| b.instructions.len()).sum();
+        assert_eq!(instructions, crate::intcode::disasm::reachable(&program).len());
+        for block in cfg.blocks.values() {
+            for (to, _) in &block.successors {
+                assert!(cfg.blocks.contains_key(to));
+            }
+        }
+    }
+}
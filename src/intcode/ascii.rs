@@ -0,0 +1,102 @@
+//! Talking to Intcode programs in ASCII
+//!
+//! Lots of programs take commands as lines of text and reply in text. `AsciiMachine` wraps an
+//! `Executor` so that it can be driven a line at a time, and keeps a transcript of the whole
+//! conversation. Output values that aren't ASCII (such as a final answer) are shown as
+//! numbers on their own line.
+
+use crate::intcode::{Executor, IntcodeFault, StopConditions, StopReason};
+
+/// Input codes for a line of text, newline included
+pub fn encode_line(line: &str) -> Vec<i64> {
+    line.bytes().map(|b| b as i64).chain(std::iter::once('\n' as i64)).collect()
+}
+
+/// Text for a single output value
+pub fn render_value(x: i64) -> String {
+    if (0..128).contains(&x) {
+        (x as u8 as char).to_string()
+    } else {
+        format!("{}\n", x)
+    }
+}
+
+pub fn render(values: &[i64]) -> String {
+    values.iter().map(|x| render_value(*x)).collect()
+}
+
+pub struct AsciiMachine {
+    pub machine: Executor,
+    transcript: String,
+}
+
+impl AsciiMachine {
+    pub fn new(program: Vec<i64>) -> AsciiMachine {
+        AsciiMachine{machine: Executor::new(program), transcript: String::new()}
+    }
+
+    /// Queue a line of input, without running anything yet
+    pub fn send_line(&mut self, line: &str) {
+        for x in encode_line(line) {
+            self.machine.push_input(x);
+        }
+        self.transcript.push_str(line);
+        self.transcript.push('\n');
+    }
+
+    /// Run until the program wants more input or halts, returning the text it wrote and which
+    /// of those happened
+    pub fn run(&mut self) -> Result<(String, StopReason), IntcodeFault> {
+        let result = self.machine.run_until(&StopConditions::default());
+        let text = render(&self.machine.output);
+        self.machine.output.clear();
+        self.transcript.push_str(&text);
+        Ok((text, result?))
+    }
+
+    /// Send a line, then run as for `run`
+    pub fn command(&mut self, line: &str) -> Result<(String, StopReason), IntcodeFault> {
+        self.send_line(line);
+        self.run()
+    }
+
+    /// Everything written by the program, and every line sent to it, in order
+    pub fn transcript(&self) -> &str {
+        &self.transcript
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::ascii::*;
+    use crate::intcode::asm::assemble;
+
+    // Prompts for a line, echoes it back and then writes 1000
+    fn echo_line() -> Vec<i64> {
+        assemble("
+                    out   #63
+            loop:   in    c
+                    out   c
+                    eq    c, #10, t
+                    jf    t, #loop
+                    out   #1000
+                    halt
+            c:      data  0
+            t:      data  0
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(encode_line("Hi"), vec![72, 105, 10]);
+        assert_eq!(render(&[72, 105, 10, 1234, 33]), "Hi\n1234\n!");
+    }
+
+    #[test]
+    fn test_conversation() {
+        let mut term = AsciiMachine::new(echo_line());
+        assert_eq!(term.run(), Ok(("?".to_string(), StopReason::NeedsInput)));
+        assert_eq!(term.command("hello"), Ok(("hello\n1000\n".to_string(), StopReason::Halted)));
+        assert_eq!(term.transcript(), "?hello\nhello\n1000\n");
+    }
+}
@@ -0,0 +1,269 @@
+//! A small assembler for writing Intcode programs by hand
+//!
+//! One statement per line, `;` starts a comment:
+//!
+//! ```text
+//! loop:   in    a             ; position mode operand, resolved to the address of `a`
+//!         add   a, #-4, a     ; `#` for immediate mode
+//!         out   rel[1]        ; `rel[...]` for relative mode
+//!         jt    #1, #loop
+//!         halt
+//! a:      data  0, 0, 5       ; raw words
+//! ```
+//!
+//! Operand values are numbers, labels, or a label with a numeric offset (`a+1`).
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::intcode::ArgMode;
+use crate::intcode::dialect::Dialect;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+fn error<T>(line: usize, message: String) -> Result<T, AsmError> {
+    Err(AsmError{line, message})
+}
+
+#[derive(Debug)]
+enum Value {
+    Number(i64),
+    Label(String, i64),
+}
+
+#[derive(Debug)]
+struct Operand {
+    mode: ArgMode,
+    value: Value,
+}
+
+enum Statement {
+    Instruction(i64, Vec<Operand>),
+    Data(Vec<Value>),
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(s: &str, line: usize) -> Result<Value, AsmError> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<i64>() {
+        return Ok(Value::Number(n));
+    }
+    // Look for a label with an offset, e.g. `table+3` or `table-1`
+    let (name, offset) = match s.find(['+', '-']) {
+        Some(split) => {
+            let offset = match s[split..].replace(' ', "").parse::<i64>() {
+                Ok(offset) => offset,
+                Err(_) => return error(line, format!("bad offset in '{}'", s)),
+            };
+            (s[..split].trim(), offset)
+        },
+        None => (s, 0),
+    };
+    if !is_identifier(name) {
+        return error(line, format!("expected a number or label, found '{}'", s));
+    }
+    Ok(Value::Label(name.to_string(), offset))
+}
+
+fn parse_operand(s: &str, line: usize) -> Result<Operand, AsmError> {
+    let s = s.trim();
+    if let Some(rest) = s.strip_prefix('#') {
+        Ok(Operand{mode: ArgMode::Immediate, value: parse_value(rest, line)?})
+    } else if let Some(rest) = s.strip_prefix("rel[") {
+        match rest.strip_suffix(']') {
+            Some(inner) => Ok(Operand{mode: ArgMode::Relative, value: parse_value(inner, line)?}),
+            None => error(line, format!("missing ']' in '{}'", s)),
+        }
+    } else {
+        Ok(Operand{mode: ArgMode::Absolute, value: parse_value(s, line)?})
+    }
+}
+
+fn split_list(s: &str) -> Vec<&str> {
+    if s.trim().is_empty() {
+        vec![]
+    } else {
+        s.split(',').collect()
+    }
+}
+
+/// Parse the statement part of a line (labels already removed)
+fn parse_statement(s: &str, line: usize, dialect: &Dialect) -> Result<Statement, AsmError> {
+    let (mnemonic, rest) = match s.find(char::is_whitespace) {
+        Some(split) => (&s[..split], &s[split..]),
+        None => (s, ""),
+    };
+    let mnemonic = mnemonic.to_lowercase();
+
+    if mnemonic == "data" {
+        let values = split_list(rest).iter().map(|v| parse_value(v, line)).collect::<Result<Vec<_>, _>>()?;
+        if values.is_empty() {
+            return error(line, "data directive needs at least one value".to_string());
+        }
+        return Ok(Statement::Data(values));
+    }
+
+    let info = match dialect.by_mnemonic(&mnemonic) {
+        Some(info) => info,
+        None => return error(line, format!("unknown mnemonic '{}'", mnemonic)),
+    };
+    let operands = split_list(rest).iter().map(|o| parse_operand(o, line)).collect::<Result<Vec<_>, _>>()?;
+    if operands.len() != info.arity() {
+        return error(line, format!("'{}' takes {} operands, found {}", info.mnemonic, info.arity(), operands.len()));
+    }
+    for (operand, is_output) in operands.iter().zip(info.outputs) {
+        if *is_output && operand.mode == ArgMode::Immediate {
+            return error(line, format!("output operand of '{}' cannot be immediate", info.mnemonic));
+        }
+    }
+    Ok(Statement::Instruction(info.opcode, operands))
+}
+
+fn resolve(value: &Value, labels: &HashMap<String, i64>, line: usize) -> Result<i64, AsmError> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        Value::Label(name, offset) => match labels.get(name) {
+            Some(addr) => Ok(addr + offset),
+            None => error(line, format!("undefined label '{}'", name)),
+        },
+    }
+}
+
+/// Assemble source text into a program that can be handed to `Executor::new`
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    assemble_with(source, &Dialect::default())
+}
+
+/// Assemble a program that uses the extra opcodes from `dialect`
+pub fn assemble_with(source: &str, dialect: &Dialect) -> Result<Vec<i64>, AsmError> {
+    let mut labels: HashMap<String, i64> = HashMap::new();
+    let mut statements: Vec<(usize, Statement)> = vec![];
+    let mut addr = 0i64;
+
+    // First pass: collect labels and work out where everything lands
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut text = match text.find(';') {
+            Some(comment) => &text[..comment],
+            None => text,
+        }.trim();
+
+        while let Some(colon) = text.find(':') {
+            let name = text[..colon].trim();
+            if !is_identifier(name) {
+                return error(line, format!("bad label '{}'", name));
+            }
+            if labels.insert(name.to_string(), addr).is_some() {
+                return error(line, format!("duplicate label '{}'", name));
+            }
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(text, line, dialect)?;
+        addr += match &statement {
+            Statement::Instruction(_, operands) => 1 + operands.len() as i64,
+            Statement::Data(values) => values.len() as i64,
+        };
+        statements.push((line, statement));
+    }
+
+    // Second pass: emit words with labels resolved
+    let mut program: Vec<i64> = vec![];
+    for (line, statement) in &statements {
+        match statement {
+            Statement::Instruction(opcode, operands) => {
+                let mut word = *opcode;
+                let mut scale = 100;
+                for operand in operands {
+                    word += operand.mode as i64 * scale;
+                    scale *= 10;
+                }
+                program.push(word);
+                for operand in operands {
+                    program.push(resolve(&operand.value, &labels, *line)?);
+                }
+            },
+            Statement::Data(values) => {
+                for value in values {
+                    program.push(resolve(value, &labels, *line)?);
+                }
+            },
+        }
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::asm::*;
+    use crate::intcode::{Executor, read_program_from_string};
+
+    #[test]
+    fn test_day7_feedback_program() {
+        let source = "
+                    in    a             ; phase setting
+                    add   a, #-4, a
+            loop:   in    b
+                    mult  b, #2, b
+                    add   b, a, b
+                    out   b
+                    add   count, #-1, count
+                    jt    count, #loop      ; keep going until count hits zero
+                    halt
+            a:      data  0
+            b:      data  0
+            count:  data  5
+        ";
+        let expected = read_program_from_string("3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,\
+            27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5".to_string()).unwrap();
+        assert_eq!(assemble(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_relative_and_offsets() {
+        let program = assemble("
+            rbo   #table
+            out   rel[1]
+            out   table+2
+            halt
+            table: data 10, 20, 30
+        ").unwrap();
+        assert_eq!(program, vec![109, 7, 204, 1, 4, 9, 99, 10, 20, 30]);
+        let mut m = Executor::new(program);
+        m.run().unwrap();
+        assert_eq!(m.output, vec![20, 30]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(assemble("add 1, 2, 3\njt #1, #nowhere").unwrap_err(),
+                   AsmError{line: 2, message: "undefined label 'nowhere'".to_string()});
+        assert_eq!(assemble("\n\nfrob 1").unwrap_err().line, 3);
+        assert_eq!(assemble("add 1, 2").unwrap_err().line, 1);
+        assert_eq!(assemble("in #4").unwrap_err().line, 1);
+        assert_eq!(assemble("x: halt\nx: halt").unwrap_err().line, 2);
+    }
+}
@@ -0,0 +1,273 @@
+//! Running many variations of a program in parallel
+//!
+//! A `Batch` holds a base program, and runs `Job`s against it, each patching memory and giving
+//! input as it likes, across a pool of threads. Results come back in job order. Parameter
+//! sweeps which are only looking for one answer can use `run_until` or `find`, which stop
+//! handing out jobs once one is found, and abandon later jobs already running.
+//!
+//! Which job is found doesn't depend on the scheduling: every job before the first that
+//! satisfies the predicate is run to the end, and everything after it is dropped.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::intcode::{Executor, IntcodeFault, StepOutcome};
+use crate::intcode::coverage::Coverage;
+
+/// Steps a job runs between checks for whether it's been cancelled
+const CANCEL_CHECK_STEPS: u64 = 10_000;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Job {
+    /// (address, value) to write before starting
+    pub patches: Vec<(usize, i64)>,
+    pub input: Vec<i64>,
+}
+
+impl Job {
+    pub fn new(patches: Vec<(usize, i64)>, input: Vec<i64>) -> Job {
+        Job{patches, input}
+    }
+
+    pub fn with_input(input: Vec<i64>) -> Job {
+        Job{patches: vec![], input}
+    }
+
+    pub fn patched(patches: Vec<(usize, i64)>) -> Job {
+        Job{patches, input: vec![]}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobResult {
+    pub memory: Vec<i64>,
+    pub output: Vec<i64>,
+    /// False if the job ran out of step budget first
+    pub halted: bool,
+    /// Only collected if asked for with `Batch::collect_coverage`
+    pub coverage: Option<Coverage>,
+}
+
+pub struct Batch {
+    program: Vec<i64>,
+    threads: usize,
+    budget: Option<u64>,
+    coverage: bool,
+}
+
+impl Batch {
+    /// Batch running `program`, with a thread per CPU
+    pub fn new(program: Vec<i64>) -> Batch {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Batch{program, threads, budget: None, coverage: false}
+    }
+
+    pub fn threads(mut self, threads: usize) -> Batch {
+        assert!(threads > 0, "A batch needs at least one thread");
+        self.threads = threads;
+        self
+    }
+
+    /// Stop each job after this many instructions
+    pub fn step_budget(mut self, budget: Option<u64>) -> Batch {
+        self.budget = budget;
+        self
+    }
+
+    pub fn collect_coverage(mut self, enabled: bool) -> Batch {
+        self.coverage = enabled;
+        self
+    }
+
+    /// Run every job
+    pub fn run(&self, jobs: &[Job]) -> Vec<Result<JobResult, IntcodeFault>> {
+        self.run_until(jobs, |_| false).into_iter().map(|result| result.unwrap()).collect()
+    }
+
+    /// Run jobs until one finishes with a result satisfying `stop`. Results are given for
+    /// that job and every job before it, and are `None` for the rest.
+    pub fn run_until<P>(&self, jobs: &[Job], stop: P) -> Vec<Option<Result<JobResult, IntcodeFault>>>
+        where P: Fn(&JobResult) -> bool + Sync {
+        self.schedule(jobs.len(), stop, |i, cancelled| self.run_job(&jobs[i], cancelled))
+    }
+
+    /// Share out `count` jobs between the threads, running job `i` with `run(i, cancelled)`.
+    /// `run` should give up and return None once `cancelled` says so.
+    fn schedule<P, R>(&self, count: usize, stop: P, run: R) -> Vec<Option<Result<JobResult, IntcodeFault>>>
+        where P: Fn(&JobResult) -> bool + Sync,
+              R: Fn(usize, &dyn Fn() -> bool) -> Option<Result<JobResult, IntcodeFault>> + Sync {
+        let next = AtomicUsize::new(0);
+        // Index of the earliest job found so far which satisfies `stop`
+        let cutoff = AtomicUsize::new(usize::MAX);
+        let results = Mutex::new(vec![None; count]);
+
+        thread::scope(|s| {
+            for _ in 0..self.threads.min(count) {
+                s.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= count || i > cutoff.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let result = match run(i, &|| i > cutoff.load(Ordering::SeqCst)) {
+                        Some(result) => result,
+                        None => continue,
+                    };
+                    if matches!(&result, Ok(r) if stop(r)) {
+                        cutoff.fetch_min(i, Ordering::SeqCst);
+                    }
+                    results.lock().unwrap()[i] = Some(result);
+                });
+            }
+        });
+
+        // Jobs after the cutoff may have finished before it was found
+        let mut results = results.into_inner().unwrap();
+        for result in results.iter_mut().skip(cutoff.into_inner().saturating_add(1)) {
+            *result = None;
+        }
+        results
+    }
+
+    /// The first job, and its result, satisfying `stop`
+    pub fn find<P>(&self, jobs: &[Job], stop: P) -> Option<(usize, JobResult)>
+        where P: Fn(&JobResult) -> bool + Sync {
+        let results = self.run_until(jobs, &stop);
+        results.into_iter().enumerate().find_map(|(i, result)| match result {
+            Some(Ok(result)) if stop(&result) => Some((i, result)),
+            _ => None,
+        })
+    }
+
+    /// Run a job to the end, or until `cancelled` says to give up on it
+    fn run_job<C: Fn() -> bool>(&self, job: &Job, cancelled: C) -> Option<Result<JobResult, IntcodeFault>> {
+        let mut m = Executor::new(self.program.clone());
+        for (addr, value) in &job.patches {
+            if let Err(fault) = m.poke(*addr, *value) {
+                return Some(Err(fault));
+            }
+        }
+        m.set_input(job.input.clone());
+        if self.coverage {
+            m.enable_coverage();
+        }
+
+        let mut left = self.budget;
+        let halted = loop {
+            let slice = left.map_or(CANCEL_CHECK_STEPS, |left| left.min(CANCEL_CHECK_STEPS));
+            m.set_step_budget(Some(slice));
+            match m.run() {
+                Err(fault) => return Some(Err(fault)),
+                Ok(StepOutcome::BudgetExhausted) => (),
+                Ok(_) => break true,
+            }
+            if let Some(left) = &mut left {
+                *left -= slice;
+                if *left == 0 {
+                    break false;
+                }
+            }
+            if cancelled() {
+                return None;
+            }
+        };
+        let coverage = m.take_coverage();
+        let memory = m.mem.into_vec().expect("Executor::new gives flat memory");
+        Some(Ok(JobResult{memory, output: m.output, halted, coverage}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use crate::intcode::asm::assemble;
+    use crate::intcode::batch::*;
+    use crate::intcode::execute_program;
+
+    // Adds up its inputs, and loops forever if the total is negative
+    fn sum() -> Vec<i64> {
+        assemble("
+            loop:   in    x
+                    add   x, total, total
+                    jt    x, #loop
+                    lt    total, #0, x
+            spin:   jt    x, #spin
+                    out   total
+                    halt
+            x:      data  0
+            total:  data  0
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_run() {
+        let jobs: Vec<Job> = (0..50).map(|n| Job::with_input((1..=n).chain(Some(0)).collect())).collect();
+        let results = Batch::new(sum()).threads(4).run(&jobs);
+        for (job, result) in jobs.iter().zip(&results) {
+            let (memory, output) = execute_program(&sum(), &job.input).unwrap();
+            let result = result.as_ref().unwrap();
+            assert_eq!((&result.memory, &result.output), (&memory, &output));
+        }
+        assert_eq!(results[10].as_ref().unwrap().output, vec![55]);
+
+        let patched = Batch::new(sum()).run(&[Job::patched(vec![(0, 98)])]);
+        assert!(patched[0].is_err());
+        let patched = Batch::new(sum()).run(&[Job::patched(vec![(1 << 40, 1)])]);
+        assert!(matches!(patched[0], Err(IntcodeFault::BadAddress{..})));
+    }
+
+    #[test]
+    fn test_budget() {
+        let results = Batch::new(sum()).step_budget(Some(25_000)).run(&[Job::with_input(vec![-1, 0]), Job::with_input(vec![0])]);
+        assert!(!results[0].as_ref().unwrap().halted);
+        assert!(results[1].as_ref().unwrap().halted);
+    }
+
+    #[test]
+    fn test_find() {
+        // The jobs just before the answer spin forever, so need the step budget to end them
+        let mut jobs: Vec<Job> = (0..8).map(|_| Job::with_input(vec![-1, 0])).collect();
+        jobs.extend((0..40).map(|n| Job::with_input(vec![n, 0])));
+        for threads in &[1, 3, 8] {
+            let batch = Batch::new(sum()).threads(*threads).step_budget(Some(50_000));
+            let (i, result) = batch.find(&jobs, |r| r.output == vec![30]).unwrap();
+            assert_eq!((i, result.output), (38, vec![30]));
+            let results = batch.run_until(&jobs, |r| r.output.first().is_some_and(|x| *x >= 20));
+            assert!(results[..8].iter().all(|r| !r.as_ref().unwrap().as_ref().unwrap().halted));
+            assert!(results[..=28].iter().all(|r| r.is_some()));
+            assert!(results[29..].iter().all(|r| r.is_none()));
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        // Job 0 is the answer, but isn't given until job 1 has started. Job 1 runs until it's
+        // cancelled, and job 2 is after the answer, so should never start.
+        let answer = JobResult{memory: vec![], output: vec![7], halted: true, coverage: None};
+        let both_started = Barrier::new(2);
+        let batch = Batch::new(sum()).threads(2);
+        let results = batch.schedule(3, |r| r.halted, |i, cancelled| match i {
+            0 => {
+                both_started.wait();
+                Some(Ok(answer.clone()))
+            },
+            1 => {
+                both_started.wait();
+                while !cancelled() {
+                    thread::yield_now();
+                }
+                None
+            },
+            _ => panic!("Job {} started after the answer was found", i),
+        });
+        assert_eq!(results, vec![Some(Ok(answer)), None, None]);
+
+        // A job that would spin forever gives up when cancelled
+        let spinner = Job::with_input(vec![-1, 0]);
+        assert_eq!(batch.run_job(&spinner, || true), None);
+        let calls = AtomicUsize::new(0);
+        assert_eq!(batch.run_job(&spinner, || calls.fetch_add(1, Ordering::SeqCst) == 2), None);
+        assert_eq!(calls.into_inner(), 3);
+    }
+}
@@ -0,0 +1,140 @@
+//! Cache of decoded instructions, keyed by PC
+//!
+//! Decoding an instruction means splitting the opcode word into digits and looking up the
+//! opcode, every time it's executed. Programs spend nearly all their time in a few loops, so
+//! `Executor` keeps what it decoded at each PC, and only decodes again once something writes
+//! over the instruction.
+//!
+//! The cache is kept in pages which clones of a machine share until one of them changes a
+//! page, the same way as `CowMemory`, so cloning a machine doesn't copy the whole cache.
+
+use std::sync::Arc;
+
+use crate::intcode::{ArgMode, RawInstruction};
+
+/// PCs at or beyond this aren't cached, so a wild jump doesn't allocate a huge table
+const MAX_CACHED_PC: usize = 1 << 20;
+
+const PAGE_SIZE: usize = 256;
+
+type Page = [Option<Decoded>; PAGE_SIZE];
+
+/// An instruction's opcode and arguments, without any operands resolved
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decoded {
+    pub opcode: i64,
+    pub modes: [ArgMode; 3],
+    pub args: [i64; 3],
+    /// As in `OpcodeInfo::outputs`, which also gives the number of arguments
+    pub outputs: &'static [bool],
+}
+
+impl Decoded {
+    pub fn size(&self) -> usize {
+        1 + self.outputs.len()
+    }
+
+    /// From a decoded instruction and the output flags for its opcode
+    pub fn new(raw: &RawInstruction, outputs: &'static [bool]) -> Decoded {
+        let mut decoded = Decoded{opcode: raw.opcode, modes: [ArgMode::Absolute; 3], args: [0; 3], outputs};
+        decoded.modes[..raw.modes.len()].copy_from_slice(&raw.modes);
+        decoded.args[..raw.args.len()].copy_from_slice(&raw.args);
+        decoded
+    }
+}
+
+impl From<&RawInstruction> for Decoded {
+    fn from(raw: &RawInstruction) -> Decoded {
+        Decoded::new(raw, raw.info().outputs)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeCache {
+    pages: Vec<Arc<Page>>,
+}
+
+impl DecodeCache {
+    pub fn get(&self, pc: usize) -> Option<Decoded> {
+        self.pages.get(pc / PAGE_SIZE).and_then(|page| page[pc % PAGE_SIZE])
+    }
+
+    pub fn insert(&mut self, pc: usize, decoded: Decoded) {
+        if pc >= MAX_CACHED_PC {
+            return;
+        }
+        if pc / PAGE_SIZE >= self.pages.len() {
+            self.pages.resize_with(pc / PAGE_SIZE + 1, || Arc::new([None; PAGE_SIZE]));
+        }
+        Arc::make_mut(&mut self.pages[pc / PAGE_SIZE])[pc % PAGE_SIZE] = Some(decoded);
+    }
+
+    /// Forget any instruction that covers `addr`, since it's about to change
+    pub fn invalidate(&mut self, addr: usize) {
+        for pc in addr.saturating_sub(3)..=addr {
+            // Only pages with something to forget are touched, so writes to data don't copy
+            // shared pages
+            if matches!(self.get(pc), Some(d) if pc + d.size() > addr) {
+                Arc::make_mut(&mut self.pages[pc / PAGE_SIZE])[pc % PAGE_SIZE] = None;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::Executor;
+    use crate::intcode::cache::*;
+    use crate::intcode::decode;
+
+    #[test]
+    fn test_invalidate() {
+        let program = vec![1101, 2, 3, 7, 1, 0, 0, 0, 99];
+        let mut cache = DecodeCache::default();
+        for pc in &[0, 4] {
+            cache.insert(*pc, Decoded::from(&decode(*pc, |a| program[a]).unwrap()));
+        }
+        assert_eq!(cache.get(0).unwrap().args, [2, 3, 7]);
+        cache.invalidate(8);
+        assert!(cache.get(4).is_some());
+        cache.invalidate(3);
+        assert!(cache.get(0).is_none());
+        assert!(cache.get(4).is_some());
+        cache.invalidate(4);
+        assert!(cache.get(4).is_none());
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        // Runs the add at 4, then turns it into a multiply and runs it again
+        let program = vec![1101, 0, 0, 100, 1001, 100, 5, 100, 1005, 200, 22, 1101, 1, 0, 200, 1101, 2, 0, 4, 1105, 1, 4, 99];
+        let mut m = Executor::new(program.clone());
+        m.run().unwrap();
+        assert_eq!(m.peek(100), 500);
+
+        let mut uncached = Executor::new(program);
+        uncached.set_decode_cache(false);
+        uncached.run().unwrap();
+        assert_eq!(m.snapshot(), uncached.snapshot());
+    }
+
+    #[test]
+    fn test_clones_share_pages() {
+        let program: Vec<i64> = (0..PAGE_SIZE as i64 * 2).map(|_| 1101).collect();
+        let mut cache = DecodeCache::default();
+        for pc in (0..program.len() - 3).step_by(4) {
+            cache.insert(pc, Decoded::from(&decode(pc, |a| program[a]).unwrap()));
+        }
+        let mut copy = cache.clone();
+        copy.invalidate(PAGE_SIZE * 2 + 10);
+        copy.invalidate(PAGE_SIZE + 1);
+        assert!(Arc::ptr_eq(&cache.pages[0], &copy.pages[0]));
+        assert!(!Arc::ptr_eq(&cache.pages[1], &copy.pages[1]));
+        assert!(copy.get(PAGE_SIZE).is_none());
+        assert!(cache.get(PAGE_SIZE).is_some());
+    }
+}
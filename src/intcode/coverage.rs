@@ -0,0 +1,179 @@
+//! Code and memory coverage
+//!
+//! Turn on with `Executor::enable_coverage`. Every PC executed is counted, and every memory
+//! cell read or written by an instruction is noted. Coverage from separate runs of the same
+//! program can be merged, and `report` lays it over the disassembled program, gcov style:
+//! instructions are marked with how often they ran, or `#####` if they never did, and data
+//! lines with whether any of their cells were read or written.
+//!
+//! The disassembly follows control flow from every PC executed as well as from address 0,
+//! so code only reached through indirect jumps, like day 7's jump table, is listed as code.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::intcode::disasm::{Entry, Listing, disassemble_from, reachable_from};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    /// Number of runs merged together
+    pub runs: usize,
+    /// Times each PC was executed
+    pub executed: BTreeMap<usize, u64>,
+    pub read: BTreeSet<usize>,
+    pub written: BTreeSet<usize>,
+}
+
+impl Coverage {
+    /// Coverage for a single run, with nothing covered yet
+    pub fn new() -> Coverage {
+        Coverage{runs: 1, ..Default::default()}
+    }
+
+    pub(crate) fn execute(&mut self, pc: usize) {
+        *self.executed.entry(pc).or_insert(0) += 1;
+    }
+
+    pub(crate) fn read(&mut self, addr: usize) {
+        self.read.insert(addr);
+    }
+
+    pub(crate) fn write(&mut self, addr: usize) {
+        self.written.insert(addr);
+    }
+
+    /// Add in coverage from other runs
+    pub fn merge(&mut self, other: &Coverage) {
+        self.runs += other.runs;
+        for (pc, hits) in &other.executed {
+            *self.executed.entry(*pc).or_insert(0) += hits;
+        }
+        self.read.extend(&other.read);
+        self.written.extend(&other.written);
+    }
+
+    /// Report against the program image that was run
+    pub fn report(&self, program: &[i64]) -> Report {
+        let roots: Vec<usize> = std::iter::once(0).chain(self.executed.keys().copied()).collect();
+        let code = reachable_from(program, &roots);
+        let covered = code.keys().filter(|pc| self.executed.contains_key(pc)).count();
+        let unexpected = self.executed.keys().filter(|pc| !code.contains_key(pc)).count();
+        Report{coverage: self.clone(), listing: disassemble_from(program, &roots), reachable: code.len(), covered, unexpected}
+    }
+}
+
+pub struct Report {
+    pub coverage: Coverage,
+    pub listing: Listing,
+    /// Instructions found by following control flow from the start and from every PC executed
+    pub reachable: usize,
+    /// How many of those were executed
+    pub covered: usize,
+    /// PCs executed that couldn't be decoded into the listing, because they overlap another
+    /// instruction or the code there has changed since
+    pub unexpected: usize,
+}
+
+impl Report {
+    /// Percentage of reachable instructions executed
+    pub fn percent(&self) -> f64 {
+        if self.reachable == 0 {
+            return 100.0;
+        }
+        100.0 * self.covered as f64 / self.reachable as f64
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let coverage = &self.coverage;
+        for line in &self.listing.lines {
+            let marker = match &line.entry {
+                Entry::Instruction(_) => match coverage.executed.get(&line.addr) {
+                    Some(hits) => hits.to_string(),
+                    None => "#####".to_string(),
+                },
+                Entry::Data(words) => {
+                    let cells = line.addr..line.addr + words.len();
+                    let read = cells.clone().any(|a| coverage.read.contains(&a));
+                    let written = cells.clone().any(|a| coverage.written.contains(&a));
+                    let executed = cells.clone().any(|a| coverage.executed.contains_key(&a));
+                    let mut marker = String::new();
+                    for (flag, c) in &[(read, 'r'), (written, 'w'), (executed, 'x')] {
+                        if *flag {
+                            marker.push(*c);
+                        }
+                    }
+                    if marker.is_empty() { "-".to_string() } else { marker }
+                },
+            };
+            writeln!(f, "{:>10} | {}", marker, self.listing.format_line(line))?;
+        }
+        writeln!(f, "Covered {} of {} reachable instructions ({:.1}%) over {} runs",
+                 self.covered, self.reachable, self.percent(), coverage.runs)?;
+        if self.unexpected > 0 {
+            writeln!(f, "Also executed {} instructions that don't fit the listing", self.unexpected)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::Executor;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::coverage::*;
+
+    // Counts down from the input, and only says goodbye if it started at 0
+    fn countdown() -> Vec<i64> {
+        assemble("
+                    in    count
+                    jf    count, #bye
+            loop:   out   count
+                    add   count, #-1, count
+                    jt    count, #loop
+                    halt
+            bye:    out   #-1
+                    halt
+            count:  data  0
+        ").unwrap()
+    }
+
+    fn run(input: i64) -> Coverage {
+        let mut m = Executor::new(countdown());
+        m.enable_coverage();
+        m.set_input(vec![input]);
+        m.run().unwrap();
+        m.take_coverage().unwrap()
+    }
+
+    #[test]
+    fn test_single_run() {
+        let coverage = run(2);
+        assert_eq!(coverage.executed[&5], 2);
+        assert!(!coverage.executed.contains_key(&15));
+        assert_eq!(coverage.written.iter().copied().collect::<Vec<_>>(), vec![18]);
+
+        let report = coverage.report(&countdown());
+        assert_eq!((report.covered, report.reachable), (6, 8));
+        let text = report.to_string();
+        assert!(text.contains("         2 | L5:       out   18"));
+        assert!(text.contains("     ##### | L15:      out   #-1"));
+        assert!(text.contains("        rw |           data  0"));
+        assert!(text.contains("Covered 6 of 8 reachable instructions (75.0%) over 1 runs"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut total = Coverage::default();
+        for input in 0..3 {
+            total.merge(&run(input));
+        }
+        assert_eq!(total.runs, 3);
+        assert_eq!(total.executed[&5], 3);
+        assert_eq!(total.executed[&0], 3);
+        let report = total.report(&countdown());
+        assert_eq!(report.covered, report.reachable);
+        assert_eq!(report.percent(), 100.0);
+    }
+}
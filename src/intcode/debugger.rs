@@ -0,0 +1,313 @@
+//! Command driven debugger for Intcode machines
+//!
+//! Each command is one line of text, and produces some text in response. Scripts are just
+//! files of commands, so a session can be replayed exactly.
+//!
+//! ```text
+//! step [n]            execute n instructions (default 1)
+//! continue            run until a breakpoint, watchpoint, input wait, halt or fault
+//! back [n]            undo n instructions (default 1)
+//! backto <pc>         undo instructions until the PC is back at pc
+//! break [pc]          set a breakpoint, or list breakpoints
+//! delete <pc>         remove a breakpoint
+//! watch <addr> [r|w]  stop when the program reads and/or writes addr (default both)
+//! unwatch <addr>      remove a watchpoint
+//! mem <addr> [count]  print memory
+//! set <addr> <value>  patch memory
+//! input <v>...        queue input values
+//! inst                show the instruction at the PC
+//! regs                show the PC and relative base register
+//! output              show all output so far
+//! quit
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::intcode::{Access, Executor, StepOutcome, decode};
+use crate::intcode::disasm::format_instruction;
+
+/// Number of instructions that can be undone with `back`
+const HISTORY_LIMIT: usize = 100_000;
+
+pub struct Debugger {
+    pub machine: Executor,
+    breakpoints: BTreeSet<u32>,
+    quit: bool,
+}
+
+fn parse_args(args: &[&str]) -> Result<Vec<i64>, String> {
+    args.iter().map(|a| a.parse::<i64>().map_err(|_| format!("bad number '{}'", a))).collect()
+}
+
+impl Debugger {
+    pub fn new(program: Vec<i64>) -> Debugger {
+        let mut machine = Executor::new(program);
+        machine.enable_history(HISTORY_LIMIT);
+        Debugger{machine, breakpoints: BTreeSet::new(), quit: false}
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    /// Execute one command line, returning the text to show the user
+    pub fn command(&mut self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            return String::new();
+        }
+        match self.dispatch(words[0], &words[1..]) {
+            Ok(text) => text,
+            Err(e) => format!("error: {}\n", e),
+        }
+    }
+
+    /// Execute each line of a script, returning a transcript of commands and responses
+    pub fn run_script(&mut self, script: &str) -> String {
+        let mut transcript = String::new();
+        for line in script.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            transcript.push_str(&format!("> {}\n", line));
+            transcript.push_str(&self.command(line));
+            if self.quit {
+                break;
+            }
+        }
+        transcript
+    }
+
+    fn dispatch(&mut self, cmd: &str, args: &[&str]) -> Result<String, String> {
+        let arg = |i: usize| match args.get(i) {
+            Some(a) => a.parse::<i64>().map_err(|_| format!("bad number '{}'", a)),
+            None => Err(format!("'{}' needs more arguments", cmd)),
+        };
+        let mut out = String::new();
+        match cmd {
+            "step" | "s" => {
+                let n = if args.is_empty() { 1 } else { arg(0)? };
+                return Ok(self.advance(Some(n.max(1) as usize)));
+            },
+            "continue" | "c" => return Ok(self.advance(None)),
+            "back" => {
+                let n = if args.is_empty() { 1 } else { arg(0)?.max(1) as usize };
+                let undone = self.machine.step_back(n);
+                if undone < n {
+                    writeln!(out, "start of history after {} steps", undone).unwrap();
+                }
+                out.push_str(&self.location());
+            },
+            "backto" => {
+                let pc = arg(0)?;
+                if !self.machine.run_back_to(pc as u32) {
+                    writeln!(out, "start of history before reaching {}", pc).unwrap();
+                }
+                out.push_str(&self.location());
+            },
+            "break" | "b" => {
+                if args.is_empty() {
+                    for pc in &self.breakpoints {
+                        writeln!(out, "breakpoint at {}", pc).unwrap();
+                    }
+                } else {
+                    self.breakpoints.insert(arg(0)? as u32);
+                }
+            },
+            "delete" | "d" => {
+                if !self.breakpoints.remove(&(arg(0)? as u32)) {
+                    return Err(format!("no breakpoint at {}", arg(0)?));
+                }
+            },
+            "watch" | "w" => {
+                let addr = arg(0)?;
+                let access = args.get(1).copied().unwrap_or("rw");
+                if !["r", "w", "rw"].contains(&access) {
+                    return Err(format!("watch access should be r, w or rw, not '{}'", access));
+                }
+                if access.contains('r') {
+                    self.machine.add_watchpoint(addr, Access::Read);
+                }
+                if access.contains('w') {
+                    self.machine.add_watchpoint(addr, Access::Write);
+                }
+            },
+            "unwatch" => self.machine.remove_watchpoint(arg(0)?),
+            "mem" | "x" => {
+                let start = arg(0)?.max(0) as usize;
+                let count = if args.len() > 1 { arg(1)?.max(1) as usize } else { 1 };
+                let words: Vec<String> = (start..start + count).map(|addr| self.machine.peek(addr).to_string()).collect();
+                for (i, chunk) in words.chunks(10).enumerate() {
+                    writeln!(out, "{}: {}", start + i * 10, chunk.join(" ")).unwrap();
+                }
+            },
+            "set" => {
+                let addr = arg(0)?;
+                if addr < 0 {
+                    return Err(format!("bad address {}", addr));
+                }
+                self.machine.poke(addr as usize, arg(1)?).map_err(|fault| fault.to_string())?;
+            },
+            "input" => {
+                for x in parse_args(args)? {
+                    self.machine.push_input(x);
+                }
+            },
+            "inst" | "i" => {
+                out.push_str(&self.location());
+                // Resolve operands on a throwaway copy, so looking doesn't disturb anything
+                if let Ok(instruction) = self.machine.clone().load() {
+                    writeln!(out, "       {:?}", instruction).unwrap();
+                }
+            },
+            "regs" => writeln!(out, "pc={} base={} halted={}", self.machine.pc(), self.machine.base_reg(), self.machine.halted()).unwrap(),
+            "output" => writeln!(out, "{:?}", self.machine.output).unwrap(),
+            "quit" | "q" => self.quit = true,
+            _ => return Err(format!("unknown command '{}'", cmd)),
+        }
+        Ok(out)
+    }
+
+    /// Describe the instruction at the PC
+    fn location(&self) -> String {
+        let pc = self.machine.pc();
+        match decode(pc as usize, |addr| self.machine.peek(addr)) {
+            Ok(raw) => format!("{:>5}: {}\n", pc, format_instruction(&raw)),
+            Err(e) => format!("{:>5}: <{:?}>\n", pc, e),
+        }
+    }
+
+    /// Step the machine up to `max_steps` times, or without limit if None, stopping early
+    /// for anything the user should know about
+    fn advance(&mut self, max_steps: Option<usize>) -> String {
+        let mut out = String::new();
+        let mut steps = 0;
+        loop {
+            match self.machine.step() {
+                Err(fault) => {
+                    writeln!(out, "fault: {}", fault).unwrap();
+                    return out;
+                },
+                Ok(StepOutcome::Halted) => {
+                    writeln!(out, "halted").unwrap();
+                    return out;
+                },
+                Ok(StepOutcome::NeedsInput) => {
+                    writeln!(out, "waiting for input").unwrap();
+                    break;
+                },
+                Ok(StepOutcome::BudgetExhausted) => {
+                    writeln!(out, "budget exhausted").unwrap();
+                    break;
+                },
+                Ok(StepOutcome::Output(x)) => writeln!(out, "out: {}", x).unwrap(),
+                Ok(StepOutcome::Running) => (),
+            }
+            steps += 1;
+
+            let hits = self.machine.take_watch_hits();
+            for hit in &hits {
+                let access = match hit.access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                };
+                writeln!(out, "watchpoint: {} {} = {} @ PC={}", access, hit.addr, hit.value, hit.pc).unwrap();
+            }
+            if !hits.is_empty() {
+                break;
+            }
+            if self.breakpoints.contains(&self.machine.pc()) {
+                writeln!(out, "breakpoint at {}", self.machine.pc()).unwrap();
+                break;
+            }
+            if max_steps == Some(steps) {
+                break;
+            }
+        }
+        out.push_str(&self.location());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::debugger::*;
+    use crate::intcode::fixtures::countdown;
+
+    #[test]
+    fn test_breakpoints_and_input() {
+        let mut dbg = Debugger::new(countdown());
+        let transcript = dbg.run_script("
+            # Stop each time around the loop
+            break 2
+            continue
+            input 3
+            continue
+            continue
+            regs
+            mem 12
+        ");
+        assert_eq!(transcript, "\
+> break 2
+> continue
+waiting for input
+    0: in    12
+> input 3
+> continue
+breakpoint at 2
+    2: out   12
+> continue
+out: 3
+breakpoint at 2
+    2: out   12
+> regs
+pc=2 base=0 halted=false
+> mem 12
+12: 2
+");
+    }
+
+    #[test]
+    fn test_watch_and_patch() {
+        let mut dbg = Debugger::new(countdown());
+        let transcript = dbg.run_script("
+            input 5
+            watch 12 w
+            c
+            set 12 1
+            c
+            inst
+            step 3
+            output
+            quit
+            step
+        ");
+        assert!(transcript.contains("watchpoint: write 12 = 5 @ PC=0\n    2: out   12"));
+        assert!(transcript.contains("out: 1\nwatchpoint: write 12 = 0 @ PC=4"));
+        assert!(transcript.contains("    8: jt    12, #2\n       JmpTrue(0, 2)"));
+        assert!(transcript.contains("halted\n> output\n[1]\n> quit\n"));
+        assert!(!transcript.contains("> step\n"));
+        assert!(dbg.quit_requested());
+    }
+
+    #[test]
+    fn test_back() {
+        let mut dbg = Debugger::new(countdown());
+        let transcript = dbg.run_script("
+            input 2
+            step 6
+            output
+            backto 2
+            output
+            back 2
+            mem 12
+            back 5
+            regs
+        ");
+        assert!(transcript.contains("> output\n[2, 1]\n> backto 2\n    2: out   12\n> output\n[2]\n"));
+        assert!(transcript.contains("> back 2\n    4: add   12, #-1, 12\n> mem 12\n12: 2\n"));
+        assert!(transcript.contains("> back 5\nstart of history after 2 steps\n    0: in    12\n> regs\npc=0 base=0 halted=false\n"));
+    }
+}
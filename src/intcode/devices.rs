@@ -0,0 +1,396 @@
+//! Memory-mapped devices
+//!
+//! A device takes over a range of addresses with `Executor::map_device`. Reads and writes that
+//! instructions make in that range go to the device instead of memory, so programs can drive
+//! hardware with ordinary instructions. Devices are told how many instructions the machine has
+//! executed, which gives them a deterministic clock.
+//!
+//! Only operand reads and writes are mapped. Instructions aren't fetched from devices, `peek`
+//! and `poke` go straight to memory, and device state isn't part of snapshots or history.
+
+use std::any::Any;
+use std::convert::TryFrom;
+
+use crate::grid::{Grid, Location, xy};
+use crate::intcode::ports::OutputSink;
+use crate::intcode::rng::Rng;
+
+pub trait Device: Any + Send {
+    /// Number of addresses taken up
+    fn size(&self) -> usize;
+    /// Read the register at `offset` into the device, after `steps` instructions
+    fn read(&mut self, offset: usize, steps: u64) -> i64;
+    /// Write the register at `offset`. Returns false if the device refuses the value, which
+    /// faults the machine.
+    fn write(&mut self, offset: usize, value: i64, steps: u64) -> bool;
+    fn box_clone(&self) -> Box<dyn Device>;
+}
+
+impl Clone for Box<dyn Device> {
+    fn clone(&self) -> Box<dyn Device> {
+        self.box_clone()
+    }
+}
+
+#[derive(Clone)]
+struct Mapping {
+    base: usize,
+    device: Box<dyn Device>,
+}
+
+impl Mapping {
+    fn offset(&self, addr: usize) -> Option<usize> {
+        addr.checked_sub(self.base).filter(|offset| *offset < self.device.size())
+    }
+}
+
+/// Devices and the addresses they're mapped at
+#[derive(Clone, Default)]
+pub struct DeviceMap {
+    mappings: Vec<Mapping>,
+}
+
+impl DeviceMap {
+    /// Map `device` at `base`. Panics if it overlaps another device.
+    pub fn map(&mut self, base: usize, device: Box<dyn Device>) {
+        let end = base + device.size();
+        for m in &self.mappings {
+            assert!(end <= m.base || base >= m.base + m.device.size(), "Device at {} overlaps the one at {}", base, m.base);
+        }
+        self.mappings.push(Mapping{base, device});
+    }
+
+    /// Value from the device at `addr`, if there is one
+    pub fn read(&mut self, addr: usize, steps: u64) -> Option<i64> {
+        self.mappings.iter_mut().find_map(|m| m.offset(addr).map(|offset| m.device.read(offset, steps)))
+    }
+
+    /// Write to the device at `addr`, if there is one, giving whether it accepted the value
+    pub fn write(&mut self, addr: usize, value: i64, steps: u64) -> Option<bool> {
+        self.mappings.iter_mut().find_map(|m| m.offset(addr).map(|offset| m.device.write(offset, value, steps)))
+    }
+
+    fn at(&self, base: usize) -> Option<&dyn Device> {
+        self.mappings.iter().find(|m| m.base == base).map(|m| m.device.as_ref())
+    }
+
+    /// The device of type `D` mapped at `base`
+    pub fn get<D: Device>(&self, base: usize) -> Option<&D> {
+        self.at(base).and_then(|device| (device as &dyn Any).downcast_ref())
+    }
+
+    pub fn get_mut<D: Device>(&mut self, base: usize) -> Option<&mut D> {
+        let device = self.mappings.iter_mut().find(|m| m.base == base)?.device.as_mut();
+        (device as &mut dyn Any).downcast_mut()
+    }
+}
+
+/// Screen of pixels, drawn by writing X, then Y, then the pixel value to its three registers.
+/// Reading the value register gives the pixel at the current X and Y.
+///
+/// It can also be used as an `OutputSink` for programs which output (x, y, value) triples,
+/// such as day 13's arcade.
+///
+/// Pixels have X and Y in `0..MAX_SIZE`. Writing one anywhere else is refused.
+#[derive(Clone)]
+pub struct Framebuffer {
+    grid: Grid<i64>,
+    x: i64,
+    y: i64,
+    /// Bounding box of everything drawn, as top left and bottom right
+    bounds: Option<(Location, Location)>,
+    /// Output values waiting for the rest of their triple
+    pending: Vec<i64>,
+    /// Triples output that were off the screen
+    dropped: usize,
+}
+
+/// Most pixels a framebuffer can be across or down
+pub const MAX_SIZE: i32 = 1024;
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer{grid: Grid::empty(), x: 0, y: 0, bounds: None, pending: vec![], dropped: 0}
+    }
+}
+
+/// Where (x, y) is, if it's on the screen
+fn location(x: i64, y: i64) -> Option<Location> {
+    let on_screen = |c: i64| i32::try_from(c).ok().filter(|c| (0..MAX_SIZE).contains(c));
+    Some(xy(on_screen(x)?, on_screen(y)?))
+}
+
+impl Framebuffer {
+    pub fn new() -> Framebuffer {
+        Framebuffer::default()
+    }
+
+    /// Set a pixel. Returns false, leaving the screen as it was, if (x, y) is off it.
+    pub fn plot(&mut self, x: i64, y: i64, value: i64) -> bool {
+        let loc = match location(x, y) {
+            Some(loc) => loc,
+            None => return false,
+        };
+        self.grid.set(&loc, Some(value));
+        self.bounds = Some(match self.bounds {
+            None => (loc, loc),
+            Some((min, max)) => (xy(min.x.min(loc.x), min.y.min(loc.y)), xy(max.x.max(loc.x), max.y.max(loc.y))),
+        });
+        true
+    }
+
+    pub fn pixel(&self, x: i64, y: i64) -> Option<i64> {
+        self.grid.peek(&location(x, y)?)
+    }
+
+    /// Number of triples output to the screen as an `OutputSink` that were dropped for being
+    /// off it
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn grid(&self) -> &Grid<i64> {
+        &self.grid
+    }
+
+    /// Every pixel drawn, with its value
+    pub fn pixels(&self) -> Vec<(Location, i64)> {
+        let mut pixels = vec![];
+        if let Some((min, max)) = self.bounds {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    if let Some(value) = self.grid.peek(&xy(x, y)) {
+                        pixels.push((xy(x, y), value));
+                    }
+                }
+            }
+        }
+        pixels
+    }
+
+    /// Draw the screen as text, a line per row, with `glyph` choosing the character for each
+    /// pixel value. Pixels never drawn are spaces.
+    pub fn render<F: Fn(i64) -> char>(&self, glyph: F) -> String {
+        let mut text = String::new();
+        if let Some((min, max)) = self.bounds {
+            for y in min.y..=max.y {
+                let row: String = (min.x..=max.x).map(|x| self.grid.peek(&xy(x, y)).map_or(' ', &glyph)).collect();
+                text.push_str(row.trim_end());
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
+
+impl Device for Framebuffer {
+    fn size(&self) -> usize {
+        3
+    }
+
+    fn read(&mut self, offset: usize, _steps: u64) -> i64 {
+        match offset {
+            0 => self.x,
+            1 => self.y,
+            _ => self.pixel(self.x, self.y).unwrap_or(0),
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: i64, _steps: u64) -> bool {
+        match offset {
+            0 => self.x = value,
+            1 => self.y = value,
+            _ => return self.plot(self.x, self.y, value),
+        }
+        true
+    }
+
+    fn box_clone(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+impl OutputSink for Framebuffer {
+    fn write(&mut self, x: i64) {
+        self.pending.push(x);
+        if let [x, y, value] = self.pending[..] {
+            if !self.plot(x, y, value) {
+                self.dropped += 1;
+            }
+            self.pending.clear();
+        }
+    }
+}
+
+/// Counts instructions. Reading gives the number executed since the timer was set, and
+/// writing a value sets it to count up from there.
+#[derive(Debug, Clone, Default)]
+pub struct Timer {
+    start: u64,
+}
+
+impl Device for Timer {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _offset: usize, steps: u64) -> i64 {
+        steps.wrapping_sub(self.start) as i64
+    }
+
+    fn write(&mut self, _offset: usize, value: i64, steps: u64) -> bool {
+        self.start = steps.wrapping_sub(value as u64);
+        true
+    }
+
+    fn box_clone(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+/// Random numbers. Reading the first register gives the next number, in 0..limit if the
+/// second register has been set to a positive limit, and otherwise any non-negative value.
+/// Writing the first register reseeds.
+#[derive(Debug, Clone)]
+pub struct Random {
+    rng: Rng,
+    limit: i64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random{rng: Rng::new(seed), limit: 0}
+    }
+}
+
+impl Device for Random {
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn read(&mut self, offset: usize, _steps: u64) -> i64 {
+        match offset {
+            0 if self.limit > 0 => self.rng.range(0, self.limit - 1),
+            0 => (self.rng.next_u64() >> 1) as i64,
+            _ => self.limit,
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: i64, _steps: u64) -> bool {
+        match offset {
+            0 => self.rng = Rng::new(value as u64),
+            _ => self.limit = value,
+        }
+        true
+    }
+
+    fn box_clone(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use crate::intcode::{Executor, IntcodeFault, read_program_from_string};
+    use crate::intcode::asm::assemble;
+    use crate::intcode::devices::*;
+
+    const SCREEN: usize = 1000;
+
+    #[test]
+    fn test_framebuffer() {
+        // Draws a diagonal line, then reads back a pixel
+        let program = assemble("
+            loop:   add   i, #0, 1000
+                    add   i, #0, 1001
+                    add   i, #1, 1002
+                    add   i, #1, i
+                    lt    i, #4, t
+                    jt    t, #loop
+                    add   #2, #0, 1000
+                    add   #2, #0, 1001
+                    out   1002
+                    halt
+            i:      data  0
+            t:      data  0
+        ").unwrap();
+        let mut m = Executor::new(program);
+        m.map_device(SCREEN, Framebuffer::new());
+        m.run().unwrap();
+        assert_eq!(m.output, vec![3]);
+        assert_eq!(m.peek(SCREEN + 2), 0);
+
+        let screen: &Framebuffer = m.device(SCREEN).unwrap();
+        assert_eq!(screen.render(|v| (b'0' + v as u8) as char), "1\n 2\n  3\n   4\n");
+        assert_eq!(screen.pixels().len(), 4);
+    }
+
+    #[test]
+    fn test_off_screen() {
+        let program = assemble("
+                    add   #100000, #0, 1000
+                    add   #100000, #0, 1001
+                    add   #1, #0, 1002
+                    halt
+        ").unwrap();
+        let mut m = Executor::new(program);
+        m.map_device(SCREEN, Framebuffer::new());
+        assert_eq!(m.run(), Err(IntcodeFault::DeviceRefused{pc: 8, instr: 1101, addr: 1002}));
+        assert!(m.device::<Framebuffer>(SCREEN).unwrap().pixels().is_empty());
+
+        let mut screen = Framebuffer::new();
+        assert!(screen.plot(0, 0, 1));
+        assert!(screen.plot(MAX_SIZE as i64 - 1, 0, 1));
+        assert!(!screen.plot(0, MAX_SIZE as i64, 1));
+        assert!(!screen.plot(-1, 0, 1));
+        assert!(!screen.plot(1 << 32, 0, 1));
+        assert_eq!(screen.pixel(1 << 32, 0), None);
+        for x in &[1 << 32, 0, 1] {
+            OutputSink::write(&mut screen, *x);
+        }
+        assert_eq!((screen.dropped(), screen.pixels().len()), (1, 2));
+    }
+
+    #[test]
+    fn test_timer_and_random() {
+        let program = assemble("
+                    add   #5, #0, 2000
+                    add   #6, #0, 3001
+                    out   3000
+                    out   3000
+                    out   2000
+                    halt
+        ").unwrap();
+        let run = |seed| {
+            let mut m = Executor::new(program.clone());
+            m.map_device(2000, Timer::default());
+            m.map_device(3000, Random::new(seed));
+            m.run().unwrap();
+            m.output
+        };
+        let output = run(1);
+        assert_eq!(output, run(1));
+        assert!(output[..2].iter().all(|x| (0..6).contains(x)));
+        // Set to 5 by the first instruction, and read by the fifth
+        assert_eq!(output[2], 9);
+    }
+
+    #[test]
+    fn test_arcade_screen() {
+        let program = read_program_from_string(include_str!("../../input/day13/input.txt").to_string()).unwrap();
+        let mut m = Executor::with_io(program, VecDeque::new(), Framebuffer::new());
+        m.run().unwrap();
+        let blocks = m.output.pixels().iter().filter(|(_, tile)| *tile == 2).count();
+        assert_eq!(blocks, 247);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps")]
+    fn test_overlap() {
+        let mut m = Executor::new(vec![99]);
+        m.map_device(10, Framebuffer::new());
+        m.map_device(12, Timer::default());
+    }
+}
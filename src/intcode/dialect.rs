@@ -0,0 +1,198 @@
+//! Extended Intcode dialects with extra opcodes
+//!
+//! A `Dialect` is the standard ten opcodes plus any registered on top. Each extra opcode is
+//! described by an `OpcodeInfo`, just like the built in ones, and given a handler. Operands
+//! are resolved before the handler is called, so it gets values for inputs and addresses for
+//! outputs, the same as the built in instructions. It works on the machine through the
+//! `Machine` trait, and returns where to jump to, if anywhere.
+//!
+//! ```ignore
+//! let mut dialect = Dialect::default();
+//! dialect.register(OpcodeInfo{opcode: 10, mnemonic: "div", outputs: &[false, false, true]}, |m, args| {
+//!     m.write_mem(args[2], args[0] / args[1])?;
+//!     Ok(None)
+//! });
+//! m.set_dialect(dialect);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::intcode::{Executor, IntcodeFault, OpcodeInfo, opcode_by_mnemonic, opcode_info};
+use crate::intcode::ports::{InputSource, OutputSink};
+
+/// What an opcode handler can do to the machine running it
+pub trait Machine {
+    fn pc(&self) -> u32;
+    fn base_reg(&self) -> i64;
+    fn set_base_reg(&mut self, base_reg: i64);
+    fn read_mem(&mut self, addr: i64) -> Result<i64, IntcodeFault>;
+    fn write_mem(&mut self, addr: i64, value: i64) -> Result<(), IntcodeFault>;
+    fn read_input(&mut self) -> Result<i64, IntcodeFault>;
+    fn write_output(&mut self, x: i64);
+    fn halt(&mut self);
+}
+
+impl<I: InputSource, O: OutputSink> Machine for Executor<I, O> {
+    fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    fn base_reg(&self) -> i64 {
+        self.base_reg
+    }
+
+    fn set_base_reg(&mut self, base_reg: i64) {
+        self.base_reg = base_reg;
+    }
+
+    fn read_mem(&mut self, addr: i64) -> Result<i64, IntcodeFault> {
+        Executor::read_mem(self, addr)
+    }
+
+    fn write_mem(&mut self, addr: i64, value: i64) -> Result<(), IntcodeFault> {
+        Executor::write_mem(self, addr, value)
+    }
+
+    fn read_input(&mut self) -> Result<i64, IntcodeFault> {
+        Executor::read_input(self)
+    }
+
+    fn write_output(&mut self, x: i64) {
+        Executor::write_output(self, x)
+    }
+
+    fn halt(&mut self) {
+        self.halted = true;
+    }
+}
+
+/// Runs an extra opcode given its resolved operands, returning the address to jump to if it
+/// jumps
+pub type Handler = dyn Fn(&mut dyn Machine, &[i64]) -> Result<Option<i64>, IntcodeFault> + Send + Sync;
+
+#[derive(Clone, Default)]
+pub struct Dialect {
+    extra: BTreeMap<i64, (OpcodeInfo, Arc<Handler>)>,
+}
+
+impl fmt::Debug for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.extra.values().map(|(info, _)| info)).finish()
+    }
+}
+
+impl Dialect {
+    /// Add an opcode. Panics if the opcode or mnemonic is already taken, or the opcode
+    /// couldn't be encoded, i.e. isn't in 0..100 or has more than three arguments.
+    pub fn register<F>(&mut self, info: OpcodeInfo, handler: F) -> &mut Dialect
+        where F: Fn(&mut dyn Machine, &[i64]) -> Result<Option<i64>, IntcodeFault> + Send + Sync + 'static {
+        assert!((0..100).contains(&info.opcode), "Opcode {} doesn't fit in two digits", info.opcode);
+        assert!(info.arity() <= 3, "Opcodes can have at most three arguments");
+        assert!(self.info(info.opcode).is_none(), "Opcode {} is already defined", info.opcode);
+        assert!(self.by_mnemonic(info.mnemonic).is_none(), "Mnemonic '{}' is already defined", info.mnemonic);
+        self.extra.insert(info.opcode, (info, Arc::new(handler)));
+        self
+    }
+
+    pub fn info(&self, opcode: i64) -> Option<OpcodeInfo> {
+        opcode_info(opcode).copied().or_else(|| self.extra.get(&opcode).map(|(info, _)| *info))
+    }
+
+    pub fn by_mnemonic(&self, mnemonic: &str) -> Option<OpcodeInfo> {
+        opcode_by_mnemonic(mnemonic).copied()
+            .or_else(|| self.extra.values().map(|(info, _)| *info).find(|info| info.mnemonic == mnemonic))
+    }
+
+    /// Handler for an extra opcode
+    pub fn handler(&self, opcode: i64) -> Option<Arc<Handler>> {
+        self.extra.get(&opcode).map(|(_, handler)| handler.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::dialect::*;
+    use crate::intcode::analysis::{analyze, analyze_with};
+    use crate::intcode::asm::assemble_with;
+    use crate::intcode::cache::Decoded;
+    use crate::intcode::decode_with;
+    use crate::intcode::disasm::{disassemble, disassemble_with, format_instruction};
+
+    /// Standard Intcode plus integer division and remainder. Dividing by zero halts.
+    fn div_mod() -> Dialect {
+        let mut dialect = Dialect::default();
+        for (opcode, mnemonic) in &[(10, "div"), (11, "mod")] {
+            let is_div = *opcode == 10;
+            dialect.register(OpcodeInfo{opcode: *opcode, mnemonic, outputs: &[false, false, true]}, move |m, args| {
+                match (args[1], is_div) {
+                    (0, _) => m.halt(),
+                    (d, true) => m.write_mem(args[2], args[0].wrapping_div(d))?,
+                    (d, false) => m.write_mem(args[2], args[0].wrapping_rem(d))?,
+                }
+                Ok(None)
+            });
+        }
+        dialect
+    }
+
+    #[test]
+    fn test_div_mod() {
+        let dialect = div_mod();
+        // Write out the decimal digits of the input, least significant first
+        let program = assemble_with("
+                    in    n
+            next:   mod   n, #10, digit
+                    out   digit
+                    div   n, #10, n
+                    jt    n, #next
+                    div   #1, #0, n
+                    out   #-1
+            n:      data  0
+            digit:  data  0
+        ", &dialect).unwrap();
+        assert_eq!(program[2], 1011);
+
+        let mut m = Executor::new(program.clone());
+        m.set_dialect(dialect);
+        m.set_input(vec![1907]);
+        m.enable_trace(true);
+        m.run().unwrap();
+        assert_eq!(m.output, vec![7, 0, 9, 1]);
+        assert_eq!(m.trace().unwrap().profile.opcode_hits["div"], 5);
+
+        let mut standard = Executor::new(program);
+        standard.set_input(vec![1907]);
+        assert_eq!(standard.run(), Err(IntcodeFault::UnknownOpcode{pc: 2, instr: 1011}));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let dialect = div_mod();
+        let program = assemble_with("
+                    in    n
+                    div   n, #10, n
+                    out   n
+                    halt
+            n:      data  0
+        ", &dialect).unwrap();
+        let raw = decode_with(2, |addr| program[addr], |opcode| dialect.info(opcode)).unwrap();
+        assert_eq!(format_instruction(&raw), "div   9, #10, 9");
+        assert_eq!(Decoded::from(&raw).size(), 4);
+
+        let listing = disassemble_with(&program, &dialect).to_string();
+        assert_eq!(assemble_with(&listing, &dialect).unwrap(), program);
+        assert_eq!(analyze_with(&program, &dialect).blocks[&0].instructions.len(), 4);
+
+        // Without the dialect, the division is just data, and so is everything after it
+        assert!(disassemble(&program).to_string().contains("data  1010"));
+        assert_eq!(analyze(&program).blocks[&0].instructions.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "already defined")]
+    fn test_clash() {
+        Dialect::default().register(OpcodeInfo{opcode: 7, mnemonic: "lt2", outputs: &[]}, |_, _| Ok(None));
+    }
+}
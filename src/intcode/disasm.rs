@@ -0,0 +1,241 @@
+//! Disassembler producing annotated listings of Intcode programs
+//!
+//! Listings are written in the syntax accepted by `intcode::asm`, with the address and raw
+//! words of each line in a trailing comment, so they can be edited and reassembled.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::intcode::{ArgMode, RawInstruction, decode_with, lookup};
+use crate::intcode::dialect::Dialect;
+
+/// Target of a jump instruction, when it is given as an immediate value
+pub fn jump_target(raw: &RawInstruction) -> Option<usize> {
+    match raw.opcode {
+        5 | 6 if raw.modes[1] == ArgMode::Immediate && raw.args[1] >= 0 => Some(raw.args[1] as usize),
+        _ => None,
+    }
+}
+
+/// Addresses that control can statically be seen to pass to after the instruction at `pc`.
+/// Both sides of every jump are followed, since a jump that looks unconditional is often a
+/// subroutine call that eventually returns to the following instruction.
+pub fn successors(pc: usize, raw: &RawInstruction) -> Vec<usize> {
+    let mut next = vec![];
+    if raw.opcode != 99 {
+        next.push(pc + raw.size());
+    }
+    if let Some(target) = jump_target(raw) {
+        next.push(target);
+    }
+    next
+}
+
+/// Decode every instruction reachable from address 0, keyed by address
+pub fn reachable(program: &[i64]) -> BTreeMap<usize, RawInstruction> {
+    reachable_from(program, &[0])
+}
+
+/// Decode every instruction reachable from any of `roots`. Extra roots, such as PCs seen
+/// at run time, find code that's only reached through indirect jumps.
+pub fn reachable_from(program: &[i64], roots: &[usize]) -> BTreeMap<usize, RawInstruction> {
+    reachable_in(program, roots, None)
+}
+
+/// `reachable_from`, understanding the extra opcodes in `dialect` if there is one
+pub(crate) fn reachable_in(program: &[i64], roots: &[usize], dialect: Option<&Dialect>) -> BTreeMap<usize, RawInstruction> {
+    let fetch = |addr: usize| program.get(addr).copied().unwrap_or(0);
+    let mut code: BTreeMap<usize, RawInstruction> = BTreeMap::new();
+    let mut claimed = vec![false; program.len()];
+    let mut todo: Vec<usize> = roots.iter().rev().copied().collect();
+
+    while let Some(pc) = todo.pop() {
+        if pc >= program.len() || code.contains_key(&pc) {
+            continue;
+        }
+        let raw = match decode_with(pc, fetch, |opcode| lookup(dialect, opcode)) {
+            Ok(raw) => raw,
+            Err(_) => continue,
+        };
+        // Don't let instructions run off the end or overlap one we already found
+        let end = pc + raw.size();
+        if end > program.len() || claimed[pc..end].iter().any(|c| *c) {
+            continue;
+        }
+        for c in &mut claimed[pc..end] {
+            *c = true;
+        }
+        todo.extend(successors(pc, &raw));
+        code.insert(pc, raw);
+    }
+    code
+}
+
+pub enum Entry {
+    Instruction(RawInstruction),
+    Data(Vec<i64>),
+}
+
+pub struct Line {
+    pub addr: usize,
+    pub label: Option<String>,
+    pub entry: Entry,
+}
+
+pub struct Listing {
+    pub lines: Vec<Line>,
+    /// Addresses of the lines with labels
+    labels: BTreeSet<usize>,
+}
+
+const DATA_PER_LINE: usize = 8;
+
+fn label_name(addr: usize) -> String {
+    format!("L{}", addr)
+}
+
+/// Disassemble a program image. Code is found by following control flow from address 0;
+/// everything else is listed as data.
+pub fn disassemble(program: &[i64]) -> Listing {
+    disassemble_from(program, &[0])
+}
+
+/// Disassemble a program written with the extra opcodes in `dialect`
+pub fn disassemble_with(program: &[i64], dialect: &Dialect) -> Listing {
+    disassemble_in(program, &[0], Some(dialect))
+}
+
+/// Disassemble, following control flow from each of `roots`
+pub fn disassemble_from(program: &[i64], roots: &[usize]) -> Listing {
+    disassemble_in(program, roots, None)
+}
+
+fn disassemble_in(program: &[i64], roots: &[usize], dialect: Option<&Dialect>) -> Listing {
+    let mut code = reachable_in(program, roots, dialect);
+    // Instructions with stray mode digits, like 1099, would be reassembled as different
+    // words, so list them as data. That leaves every instruction's words as in the program.
+    code.retain(|pc, raw| raw.words() == program[*pc..*pc + raw.size()]);
+
+    // Only label jump targets which land on the start of a line
+    let inside_instruction = |addr: usize| code.range(..addr).next_back().is_some_and(|(pc, raw)| addr < pc + raw.size());
+    let targets: BTreeSet<usize> = code.values()
+        .filter_map(jump_target)
+        .filter(|t| *t < program.len() && !inside_instruction(*t))
+        .collect();
+
+    let mut lines: Vec<Line> = vec![];
+    let mut addr = 0;
+    while addr < program.len() {
+        let label = if targets.contains(&addr) { Some(label_name(addr)) } else { None };
+        let entry = match code.get(&addr) {
+            Some(raw) => Entry::Instruction(raw.clone()),
+            None => {
+                // Gather data up to the next instruction or label
+                let mut end = addr + 1;
+                while end < program.len() && end - addr < DATA_PER_LINE && !code.contains_key(&end) && !targets.contains(&end) {
+                    end += 1;
+                }
+                Entry::Data(program[addr..end].to_vec())
+            },
+        };
+        let size = match &entry {
+            Entry::Instruction(raw) => raw.size(),
+            Entry::Data(words) => words.len(),
+        };
+        lines.push(Line{addr, label, entry});
+        addr += size;
+    }
+    let labels = lines.iter().filter(|l| l.label.is_some()).map(|l| l.addr).collect();
+    Listing{lines, labels}
+}
+
+fn format_operand(raw: &RawInstruction, i: usize, labelled: bool) -> String {
+    let arg = raw.args[i];
+    match raw.modes[i] {
+        ArgMode::Immediate if labelled => format!("#{}", label_name(arg as usize)),
+        ArgMode::Immediate => format!("#{}", arg),
+        ArgMode::Absolute => format!("{}", arg),
+        ArgMode::Relative => format!("rel[{}]", arg),
+    }
+}
+
+fn format_with_labels<F: Fn(usize) -> bool>(raw: &RawInstruction, is_label: F) -> String {
+    let target_labelled = jump_target(raw).is_some_and(is_label);
+    let operands: Vec<String> = (0..raw.args.len()).map(|i| format_operand(raw, i, i == 1 && target_labelled)).collect();
+    format!("{:<6}{}", raw.info().mnemonic, operands.join(", "))
+}
+
+/// Format a single instruction in assembler syntax
+pub fn format_instruction(raw: &RawInstruction) -> String {
+    format_with_labels(raw, |_| false)
+}
+
+fn join(words: &[i64], separator: &str) -> String {
+    words.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(separator)
+}
+
+impl Listing {
+    fn is_label(&self, addr: usize) -> bool {
+        self.labels.contains(&addr)
+    }
+
+    /// A line as it appears in the listing, without the newline
+    pub fn format_line(&self, line: &Line) -> String {
+        let label = match &line.label {
+            Some(label) => format!("{}:", label),
+            None => String::new(),
+        };
+        let (statement, words) = match &line.entry {
+            Entry::Instruction(raw) => (format_with_labels(raw, |t| self.is_label(t)), raw.words()),
+            Entry::Data(words) => (format!("{:<6}{}", "data", join(words, ", ")), words.clone()),
+        };
+        format!("{:<10}{:<36}; {:>5}: {}", label, statement, line.addr, join(&words, ","))
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", self.format_line(line))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::disasm::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::fixtures::day9;
+
+    #[test]
+    fn test_labels_and_data() {
+        let program = assemble("
+                    in    count
+            loop:   out   count
+                    add   count, #-1, count
+                    jt    count, #loop
+                    halt
+            count:  data  0, -7
+        ").unwrap();
+        let listing = disassemble(&program);
+        let text = listing.to_string();
+        assert!(text.contains("L2:       out   12"));
+        assert!(text.contains("jt    12, #L2"));
+        assert!(text.contains("data  0, -7"));
+        assert_eq!(listing.lines.len(), 6);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let program = day9();
+        let listing = disassemble(&program).to_string();
+        assert_eq!(assemble(&listing).unwrap(), program);
+
+        // Mode digits for arguments the opcode doesn't have
+        let program = vec![10004, 3, 1099, 7];
+        let listing = disassemble(&program);
+        assert!(listing.lines.iter().all(|line| matches!(line.entry, Entry::Data(_))));
+        assert_eq!(assemble(&listing.to_string()).unwrap(), program);
+    }
+}
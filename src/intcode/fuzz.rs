@@ -0,0 +1,414 @@
+//! Differential fuzzing of `Executor` against a minimal reference interpreter
+//!
+//! `generate` makes random programs from well formed instructions, with addresses kept within
+//! the program so that they read and write each other's code and data. Each is run by both
+//! `Executor` and the deliberately simple interpreter in `reference`, and the output, final
+//! memory, final PC and how the run ended are compared. A program that shows a difference is
+//! shrunk to something small that still does, to make a readable reproducer.
+//!
+//! Writing at or above `MAX_ADDR` is out of range for both: `Executor`'s flat memory faults
+//! with a bad address, and the reference stops, so the two must still agree on where.
+
+use crate::intcode::{Executor, IntcodeFault, StepOutcome};
+use crate::intcode::memory::MAX_VEC_EXTENT;
+use crate::intcode::rng::Rng;
+
+/// Writes at or beyond this address are out of range, as they are for flat memory
+pub const MAX_ADDR: usize = MAX_VEC_EXTENT;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GenConfig {
+    pub instructions: usize,
+    /// Words of data after the code
+    pub data: usize,
+    /// Most input values to give
+    pub inputs: usize,
+    pub max_steps: u64,
+}
+
+impl Default for GenConfig {
+    fn default() -> GenConfig {
+        GenConfig{instructions: 12, data: 8, inputs: 3, max_steps: 1000}
+    }
+}
+
+/// A program and the input to run it with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub program: Vec<i64>,
+    pub input: Vec<i64>,
+}
+
+/// Opcode and its arguments, true for those written to
+const SHAPES: [(i64, &[bool]); 10] = [
+    (1, &[false, false, true]),
+    (2, &[false, false, true]),
+    (3, &[true]),
+    (4, &[false]),
+    (5, &[false, false]),
+    (6, &[false, false]),
+    (7, &[false, false, true]),
+    (8, &[false, false, true]),
+    (9, &[false]),
+    (99, &[]),
+];
+
+pub fn generate(rng: &mut Rng, config: &GenConfig) -> Case {
+    let shapes: Vec<(i64, &[bool])> = (0..config.instructions).map(|_| SHAPES[rng.below(SHAPES.len())]).collect();
+    let mut starts = vec![];
+    let mut code_len = 0;
+    for (_, outputs) in &shapes {
+        starts.push(code_len);
+        code_len += 1 + outputs.len();
+    }
+    let len = (code_len + 1 + config.data) as i64;
+
+    let mut program = vec![];
+    for (opcode, outputs) in &shapes {
+        let mut cmd = *opcode;
+        let mut args = vec![];
+        for (i, is_output) in outputs.iter().enumerate() {
+            let is_target = (*opcode == 5 || *opcode == 6) && i == 1;
+            let mode = match rng.below(3) {
+                1 if *is_output => 0,
+                mode => mode,
+            };
+            let arg = match mode {
+                // Mostly jump to the start of an instruction, sometimes anywhere
+                1 if is_target && rng.below(4) > 0 => starts[rng.below(starts.len())] as i64,
+                1 if is_target => rng.range(0, len),
+                1 if *opcode == 9 => rng.range(-3, 3),
+                1 => rng.range(-10, 10),
+                _ => rng.range(0, len - 1),
+            };
+            cmd += mode as i64 * 10i64.pow(2 + i as u32);
+            args.push(arg);
+        }
+        program.push(cmd);
+        program.extend(args);
+    }
+    program.push(99);
+    program.extend((0..config.data).map(|_| rng.range(-100, 100)));
+
+    let input = (0..rng.below(config.inputs + 1)).map(|_| rng.range(-100, 100)).collect();
+    Case{program, input}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Halted,
+    Faulted,
+    OutOfSteps,
+    /// Tried to write at or beyond `MAX_ADDR`
+    TooBig,
+}
+
+/// What a machine did with a case
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub outcome: Outcome,
+    pub pc: usize,
+    pub output: Vec<i64>,
+    /// Final memory, without trailing zeros, since how far memory has grown isn't
+    /// something a program can see
+    pub memory: Vec<i64>,
+}
+
+fn trim(mut memory: Vec<i64>) -> Vec<i64> {
+    while memory.last() == Some(&0) {
+        memory.pop();
+    }
+    memory
+}
+
+/// The spec, written out as directly as possible, sharing nothing with `Executor`
+pub mod reference {
+    use super::{MAX_ADDR, Outcome, Run, trim};
+
+    struct Machine {
+        mem: Vec<i64>,
+        pc: usize,
+        base: i64,
+        input: Vec<i64>,
+        output: Vec<i64>,
+    }
+
+    enum Stop {
+        Halt,
+        Fault,
+        TooBig,
+    }
+
+    impl Machine {
+        fn read(&self, addr: i64) -> Result<i64, Stop> {
+            if addr < 0 {
+                return Err(Stop::Fault);
+            }
+            Ok(self.mem.get(addr as usize).copied().unwrap_or(0))
+        }
+
+        fn write(&mut self, addr: i64, value: i64) -> Result<(), Stop> {
+            if addr < 0 {
+                return Err(Stop::Fault);
+            }
+            let addr = addr as usize;
+            if addr >= MAX_ADDR {
+                return Err(Stop::TooBig);
+            }
+            if addr >= self.mem.len() {
+                self.mem.resize(addr + 1, 0);
+            }
+            self.mem[addr] = value;
+            Ok(())
+        }
+
+        fn mode(&self, n: u32) -> Result<i64, Stop> {
+            match self.mem[self.pc] / 10i64.pow(n + 1) % 10 {
+                mode @ 0..=2 => Ok(mode),
+                _ => Err(Stop::Fault),
+            }
+        }
+
+        /// Value of parameter `n`, counting from 1
+        fn get(&self, n: u32) -> Result<i64, Stop> {
+            let param = self.read(self.pc as i64 + n as i64)?;
+            match self.mode(n)? {
+                0 => self.read(param),
+                1 => Ok(param),
+                _ => self.read(self.base.wrapping_add(param)),
+            }
+        }
+
+        /// Address given by parameter `n`, which is written to
+        fn addr(&self, n: u32) -> Result<i64, Stop> {
+            let param = self.read(self.pc as i64 + n as i64)?;
+            match self.mode(n)? {
+                0 => Ok(param),
+                1 => Err(Stop::Fault),
+                _ => Ok(self.base.wrapping_add(param)),
+            }
+        }
+
+        fn jump(&mut self, to: i64) -> Result<(), Stop> {
+            if to < 0 || to > u32::MAX as i64 {
+                return Err(Stop::Fault);
+            }
+            self.pc = to as usize;
+            Ok(())
+        }
+
+        fn step(&mut self) -> Result<(), Stop> {
+            let op = self.read(self.pc as i64)?;
+            if op < 0 {
+                return Err(Stop::Fault);
+            }
+            match op % 100 {
+                1 | 2 | 7 | 8 => {
+                    let (a, b, c) = (self.get(1)?, self.get(2)?, self.addr(3)?);
+                    let value = match op % 100 {
+                        1 => a.wrapping_add(b),
+                        2 => a.wrapping_mul(b),
+                        7 => (a < b) as i64,
+                        _ => (a == b) as i64,
+                    };
+                    self.write(c, value)?;
+                    self.pc += 4;
+                },
+                3 => {
+                    let addr = self.addr(1)?;
+                    if self.input.is_empty() {
+                        return Err(Stop::Fault);
+                    }
+                    let value = self.input.remove(0);
+                    self.write(addr, value)?;
+                    self.pc += 2;
+                },
+                4 => {
+                    let value = self.get(1)?;
+                    self.output.push(value);
+                    self.pc += 2;
+                },
+                5 | 6 => {
+                    let (cond, to) = (self.get(1)?, self.get(2)?);
+                    if (cond != 0) == (op % 100 == 5) {
+                        self.jump(to)?;
+                    } else {
+                        self.pc += 3;
+                    }
+                },
+                9 => {
+                    self.base = self.base.wrapping_add(self.get(1)?);
+                    self.pc += 2;
+                },
+                99 => {
+                    self.pc += 1;
+                    return Err(Stop::Halt);
+                },
+                _ => return Err(Stop::Fault),
+            }
+            Ok(())
+        }
+    }
+
+    pub fn run(program: &[i64], input: &[i64], max_steps: u64) -> Run {
+        let mut m = Machine{mem: program.to_vec(), pc: 0, base: 0, input: input.to_vec(), output: vec![]};
+        let mut outcome = Outcome::OutOfSteps;
+        for _ in 0..max_steps {
+            match m.step() {
+                Ok(()) => (),
+                Err(Stop::Halt) => outcome = Outcome::Halted,
+                Err(Stop::Fault) => outcome = Outcome::Faulted,
+                Err(Stop::TooBig) => outcome = Outcome::TooBig,
+            }
+            if outcome != Outcome::OutOfSteps {
+                break;
+            }
+        }
+        Run{outcome, pc: m.pc, output: m.output, memory: trim(m.mem)}
+    }
+}
+
+pub fn run_executor(case: &Case, max_steps: u64) -> Run {
+    let mut m = Executor::new(case.program.clone());
+    m.set_input(case.input.clone());
+    m.set_step_budget(Some(max_steps));
+    let outcome = match m.run() {
+        Ok(StepOutcome::BudgetExhausted) => Outcome::OutOfSteps,
+        Ok(_) => Outcome::Halted,
+        Err(IntcodeFault::BadAddress{addr, ..}) if addr >= MAX_ADDR as i64 => Outcome::TooBig,
+        Err(_) => Outcome::Faulted,
+    };
+    let pc = m.pc() as usize;
+    let memory = trim(m.mem.into_vec().expect("Executor::new gives flat memory"));
+    Run{outcome, pc, output: m.output, memory}
+}
+
+/// The two runs of a case that didn't agree
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub expected: Run,
+    pub actual: Run,
+}
+
+/// Run a case both ways
+pub fn check(case: &Case, max_steps: u64) -> Result<(), Box<Mismatch>> {
+    let expected = reference::run(&case.program, &case.input, max_steps);
+    let actual = run_executor(case, max_steps);
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(Box::new(Mismatch{expected, actual}))
+    }
+}
+
+/// Candidates one step simpler than `case`
+fn simplifications(case: &Case) -> Vec<Case> {
+    let mut candidates = vec![];
+    for i in 0..case.input.len() {
+        let mut input = case.input.clone();
+        input.remove(i);
+        candidates.push(Case{program: case.program.clone(), input});
+    }
+    for size in (1..=4).rev() {
+        for start in 0..case.program.len().saturating_sub(size - 1) {
+            let mut program = case.program.clone();
+            program.drain(start..start + size);
+            candidates.push(Case{program, input: case.input.clone()});
+        }
+    }
+    for i in 0..case.program.len() {
+        let word = case.program[i];
+        for simpler in &[0, word / 2, word.signum() * (word.abs() % 100)] {
+            if simpler.abs() < word.abs() {
+                let mut program = case.program.clone();
+                program[i] = *simpler;
+                candidates.push(Case{program, input: case.input.clone()});
+            }
+        }
+    }
+    candidates
+}
+
+/// Shrink a case for as long as `fails` holds, taking the first simplification that keeps
+/// it failing each time round
+pub fn shrink<F: Fn(&Case) -> bool>(case: &Case, fails: F) -> Case {
+    let mut case = case.clone();
+    while let Some(simpler) = simplifications(&case).into_iter().find(|c| fails(c)) {
+        case = simpler;
+    }
+    case
+}
+
+/// A shrunk case that `Executor` gets wrong
+#[derive(Debug, Clone)]
+pub struct Failure {
+    /// Seed that generated the original case
+    pub seed: u64,
+    pub original: Case,
+    pub minimal: Case,
+    pub mismatch: Mismatch,
+}
+
+/// Check `runs` random cases, one from each seed starting at `seed`, stopping at the first
+/// failure
+pub fn fuzz(seed: u64, runs: u64, config: &GenConfig) -> Option<Failure> {
+    for seed in seed..seed + runs {
+        let original = generate(&mut Rng::new(seed), config);
+        if check(&original, config.max_steps).is_err() {
+            let minimal = shrink(&original, |c| check(c, config.max_steps).is_err());
+            let mismatch = *check(&minimal, config.max_steps).unwrap_err();
+            return Some(Failure{seed, original, minimal, mismatch});
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::fuzz::*;
+    use crate::intcode::decode;
+
+    #[test]
+    fn test_generate() {
+        let config = GenConfig::default();
+        let case = generate(&mut Rng::new(7), &config);
+        assert_eq!(case, generate(&mut Rng::new(7), &config));
+        // Every instruction in the code part decodes
+        let mut pc = 0;
+        for _ in 0..config.instructions {
+            pc += decode(pc, |a| case.program[a]).unwrap().size();
+        }
+        assert_eq!(case.program[pc], 99);
+        assert_eq!(case.program.len(), pc + 1 + config.data);
+    }
+
+    #[test]
+    fn test_agrees() {
+        assert!(fuzz(0, 2000, &GenConfig::default()).is_none());
+        let long = GenConfig{instructions: 40, data: 20, ..GenConfig::default()};
+        assert!(fuzz(1 << 32, 500, &long).is_none());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let case = Case{program: vec![1101, 1, 1, 1 << 40, 99], input: vec![]};
+        assert_eq!(check(&case, 10), Ok(()));
+        assert_eq!(run_executor(&case, 10).outcome, Outcome::TooBig);
+        let case = Case{program: vec![3, MAX_ADDR as i64 - 1, 99], input: vec![5]};
+        assert_eq!(check(&case, 10), Ok(()));
+        assert_eq!(run_executor(&case, 10).outcome, Outcome::Halted);
+    }
+
+    #[test]
+    fn test_shrink() {
+        // Pretend that producing any output is a bug
+        let config = GenConfig::default();
+        let outputs = |c: &Case| !reference::run(&c.program, &c.input, config.max_steps).output.is_empty();
+        let case = (0..).map(|seed| generate(&mut Rng::new(seed), &config)).find(|c| outputs(c)).unwrap();
+        let minimal = shrink(&case, outputs);
+        assert!(outputs(&minimal));
+        assert_eq!(minimal.program.len(), 1);
+        assert_eq!(minimal.program[0] % 100, 4);
+        assert!(minimal.input.is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+//! Undo log for stepping machines backwards
+//!
+//! Turn on with `Executor::enable_history`. Before each instruction the PC, relative base and
+//! size of memory are noted, and as it runs the old value of every memory cell written, any
+//! input consumed and whether output was produced. Undoing puts all of that back. Only the
+//! most recent `limit` instructions are kept, so long runs don't use unbounded memory.
+//!
+//! Changes made from outside the program, such as `poke`, aren't recorded. Neither are traces
+//! or watchpoint hits, which describe what happened rather than the machine's state.
+
+use std::collections::VecDeque;
+
+use crate::intcode::Executor;
+
+/// Everything needed to undo one instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndoRecord {
+    pub pc: u32,
+    pub base_reg: i64,
+    pub halted: bool,
+    /// Memory extent before the instruction, so growth can be undone too
+    pub extent: usize,
+    /// (address, old value) for each write, in the order they happened
+    pub writes: Vec<(usize, i64)>,
+    pub input: Option<i64>,
+    pub output: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    limit: usize,
+    records: VecDeque<UndoRecord>,
+    current: Option<UndoRecord>,
+}
+
+impl History {
+    /// History of at most `limit` instructions
+    pub fn new(limit: usize) -> History {
+        History{limit, records: VecDeque::new(), current: None}
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Number of instructions that can be undone
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub(crate) fn begin(&mut self, pc: u32, base_reg: i64, halted: bool, extent: usize) {
+        self.current = Some(UndoRecord{pc, base_reg, halted, extent, writes: vec![], input: None, output: false});
+    }
+
+    pub(crate) fn write(&mut self, addr: usize, old: i64) {
+        if let Some(record) = &mut self.current {
+            record.writes.push((addr, old));
+        }
+    }
+
+    pub(crate) fn input(&mut self, x: i64) {
+        if let Some(record) = &mut self.current {
+            record.input = Some(x);
+        }
+    }
+
+    pub(crate) fn output(&mut self) {
+        if let Some(record) = &mut self.current {
+            record.output = true;
+        }
+    }
+
+    /// The instruction begun completed, so keep its record
+    pub(crate) fn commit(&mut self) {
+        if let Some(record) = self.current.take() {
+            self.records.push_back(record);
+            while self.records.len() > self.limit {
+                self.records.pop_front();
+            }
+        }
+    }
+
+    /// The instruction begun didn't complete
+    pub(crate) fn abandon(&mut self) {
+        self.current = None;
+    }
+
+    fn pop(&mut self) -> Option<UndoRecord> {
+        self.records.pop_back()
+    }
+}
+
+impl Executor {
+    /// Undo the last instruction, returning false if there's no history left
+    fn undo(&mut self) -> bool {
+        let record = match self.history.as_mut().and_then(|h| h.pop()) {
+            Some(record) => record,
+            None => return false,
+        };
+        for (addr, old) in record.writes.into_iter().rev() {
+            self.store(addr, old);
+        }
+        if record.extent < self.mem.extent() {
+            self.mem.truncate(record.extent);
+            if let Some(cache) = &mut self.cache {
+                cache.clear();
+            }
+        }
+        if let Some(x) = record.input {
+            self.input.push_front(x);
+        }
+        if record.output {
+            self.output.pop();
+        }
+        self.pc = record.pc;
+        self.base_reg = record.base_reg;
+        self.halted = record.halted;
+        true
+    }
+
+    /// Undo up to `n` instructions, returning how many were undone
+    pub fn step_back(&mut self, n: usize) -> usize {
+        (0..n).take_while(|_| self.undo()).count()
+    }
+
+    /// Undo instructions until the PC is back at `pc`, undoing at least one. Returns false,
+    /// having undone everything, if the history doesn't go back that far.
+    pub fn run_back_to(&mut self, pc: u32) -> bool {
+        while self.undo() {
+            if self.pc == pc {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Executor, StopConditions};
+    use crate::intcode::asm::assemble;
+    use crate::intcode::memory::MemoryKind;
+
+    // Counts down from the input, then moves the relative base and writes well past the end
+    fn countdown() -> Vec<i64> {
+        assemble("
+                    in    count
+            loop:   out   count
+                    add   count, #-1, count
+                    jt    count, #loop
+                    rbo   #7
+                    add   #1, #2, 1000
+                    halt
+            count:  data  0
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_step_back() {
+        for kind in &[MemoryKind::Flat, MemoryKind::CopyOnWrite] {
+            let mut m = Executor::with_memory(kind.load(countdown()));
+            m.set_input(vec![3, 99]);
+            m.enable_history(1000);
+            let start = m.snapshot();
+            m.outputs().next();
+            let after_first_output = m.snapshot();
+            m.run_until(&StopConditions::default()).unwrap();
+            assert!(m.halted());
+            assert_eq!(m.base_reg(), 7);
+            assert_eq!(m.peek(1000), 3);
+
+            assert_eq!(m.step_back(1), 1);
+            assert!(!m.halted());
+            assert!(m.run_back_to(2));
+            assert_eq!(m.output, vec![3, 2]);
+            assert_eq!(m.step_back(1000), 7);
+            assert_eq!(m.snapshot(), start);
+
+            m.outputs().next();
+            assert_eq!(m.snapshot(), after_first_output);
+        }
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut m = Executor::new(countdown());
+        m.set_input(vec![3]);
+        m.enable_history(4);
+        m.run().unwrap();
+        assert_eq!(m.history().unwrap().len(), 4);
+        assert!(!m.run_back_to(0));
+        assert_eq!(m.pc(), 8);
+        assert_eq!(m.step_back(1), 0);
+    }
+}
@@ -0,0 +1,383 @@
+//! Memory backends for `Executor`
+//!
+//! Intcode memory is conceptually infinite and zero filled. `FlatMemory` keeps it in one Vec,
+//! which is fastest for ordinary programs, but won't grow past `MAX_VEC_EXTENT`: writing
+//! higher is a bad address fault rather than a giant allocation. `PagedMemory` only allocates the pages that are
+//! written, so scattered huge addresses are cheap. `CowMemory` is paged as well, but shares
+//! pages between clones until one of them writes, so cloning a machine to explore different
+//! paths (as day 15 does) costs a pointer per page instead of a copy of everything.
+//!
+//! An `Image` is a sparse copy of any kind of memory, holding only the runs of non-zero
+//! values, for snapshots and comparisons that mustn't blow up on a huge address.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub trait Memory: Send {
+    /// Value at `addr`, zero if it has never been written
+    fn read(&self, addr: usize) -> i64;
+    /// Store `value` at `addr`, which must be below `limit`
+    fn write(&mut self, addr: usize, value: i64);
+    /// One past the highest address that can be written. Programs writing at or above it get
+    /// a bad address fault.
+    fn limit(&self) -> usize {
+        usize::MAX
+    }
+    /// One past the highest address that has been written
+    fn extent(&self) -> usize;
+    /// Forget everything from `extent` on, as if it had never been written
+    fn truncate(&mut self, extent: usize);
+    fn box_clone(&self) -> Box<dyn Memory>;
+    fn kind(&self) -> MemoryKind;
+    /// The stretches of memory actually held, as (start address, values), in address order.
+    /// Anything outside them is zero.
+    fn segments(&self) -> Vec<(usize, &[i64])>;
+    /// Contents from address zero up to the extent, if the memory is flat and so already
+    /// held that way. Other kinds give None rather than filling in what could be a huge gap.
+    fn into_vec(self: Box<Self>) -> Option<Vec<i64>>;
+}
+
+impl Clone for Box<dyn Memory> {
+    fn clone(&self) -> Box<dyn Memory> {
+        self.box_clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    Flat,
+    Paged,
+    CopyOnWrite,
+}
+
+impl MemoryKind {
+    /// Create memory of this kind holding `program`
+    pub fn load(self, program: Vec<i64>) -> Box<dyn Memory> {
+        match self {
+            MemoryKind::Flat => Box::new(FlatMemory(program)),
+            MemoryKind::Paged => Box::new(PagedMemory::from(program)),
+            MemoryKind::CopyOnWrite => Box::new(CowMemory::from(program)),
+        }
+    }
+}
+
+/// Plain Vec, grown as needed by writes, up to `MAX_VEC_EXTENT` words
+#[derive(Clone)]
+pub struct FlatMemory(pub Vec<i64>);
+
+impl Memory for FlatMemory {
+    fn read(&self, addr: usize) -> i64 {
+        self.0.get(addr).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, addr: usize, value: i64) {
+        assert!(addr < MAX_VEC_EXTENT, "Flat memory write at {}", addr);
+        if addr >= self.0.len() {
+            self.0.resize(addr + 1, 0);
+        }
+        self.0[addr] = value;
+    }
+
+    fn limit(&self) -> usize {
+        MAX_VEC_EXTENT
+    }
+
+    fn extent(&self) -> usize {
+        self.0.len()
+    }
+
+    fn truncate(&mut self, extent: usize) {
+        self.0.truncate(extent);
+    }
+
+    fn box_clone(&self) -> Box<dyn Memory> {
+        Box::new(self.clone())
+    }
+
+    fn kind(&self) -> MemoryKind {
+        MemoryKind::Flat
+    }
+
+    fn segments(&self) -> Vec<(usize, &[i64])> {
+        vec![(0, &self.0[..])]
+    }
+
+    fn into_vec(self: Box<Self>) -> Option<Vec<i64>> {
+        Some(self.0)
+    }
+}
+
+pub const PAGE_SIZE: usize = 256;
+
+type Page = [i64; PAGE_SIZE];
+
+/// Pages of memory, keyed by page number. `P` is how a page is held, so the same code serves
+/// for owned and shared pages.
+#[derive(Clone, Default)]
+pub struct Pages<P> {
+    pages: HashMap<usize, P>,
+    extent: usize,
+}
+
+pub type PagedMemory = Pages<Box<Page>>;
+pub type CowMemory = Pages<Arc<Page>>;
+
+/// Ways of holding a page
+pub trait PageRef: Clone + Send + 'static {
+    const KIND: MemoryKind;
+
+    fn zeroed() -> Self;
+    fn get(&self) -> &Page;
+    fn get_mut(&mut self) -> &mut Page;
+}
+
+impl PageRef for Box<Page> {
+    const KIND: MemoryKind = MemoryKind::Paged;
+
+    fn zeroed() -> Self {
+        Box::new([0; PAGE_SIZE])
+    }
+
+    fn get(&self) -> &Page {
+        self
+    }
+
+    fn get_mut(&mut self) -> &mut Page {
+        self
+    }
+}
+
+impl PageRef for Arc<Page> {
+    const KIND: MemoryKind = MemoryKind::CopyOnWrite;
+
+    fn zeroed() -> Self {
+        Arc::new([0; PAGE_SIZE])
+    }
+
+    fn get(&self) -> &Page {
+        self
+    }
+
+    /// Copies the page first if any other memory is sharing it
+    fn get_mut(&mut self) -> &mut Page {
+        Arc::make_mut(self)
+    }
+}
+
+impl<P: PageRef> Pages<P> {
+    pub fn pages_allocated(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+impl<P: PageRef> From<Vec<i64>> for Pages<P> {
+    fn from(program: Vec<i64>) -> Pages<P> {
+        let mut memory = Pages{pages: HashMap::new(), extent: 0};
+        for (addr, value) in program.into_iter().enumerate() {
+            memory.write(addr, value);
+        }
+        memory
+    }
+}
+
+impl<P: PageRef> Memory for Pages<P> {
+    fn read(&self, addr: usize) -> i64 {
+        match self.pages.get(&(addr / PAGE_SIZE)) {
+            Some(page) => page.get()[addr % PAGE_SIZE],
+            None => 0,
+        }
+    }
+
+    fn write(&mut self, addr: usize, value: i64) {
+        let page = self.pages.entry(addr / PAGE_SIZE).or_insert_with(P::zeroed);
+        page.get_mut()[addr % PAGE_SIZE] = value;
+        self.extent = self.extent.max(addr + 1);
+    }
+
+    fn extent(&self) -> usize {
+        self.extent
+    }
+
+    fn truncate(&mut self, extent: usize) {
+        if extent >= self.extent {
+            return;
+        }
+        self.pages.retain(|page, _| page * PAGE_SIZE < extent);
+        if let Some(page) = self.pages.get_mut(&(extent / PAGE_SIZE)) {
+            page.get_mut()[extent % PAGE_SIZE..].iter_mut().for_each(|x| *x = 0);
+        }
+        self.extent = extent;
+    }
+
+    fn box_clone(&self) -> Box<dyn Memory> {
+        Box::new(self.clone())
+    }
+
+    fn kind(&self) -> MemoryKind {
+        P::KIND
+    }
+
+    fn segments(&self) -> Vec<(usize, &[i64])> {
+        let mut segments: Vec<(usize, &[i64])> = self.pages.iter()
+            .map(|(page, p)| (page * PAGE_SIZE, &p.get()[..PAGE_SIZE.min(self.extent - page * PAGE_SIZE)]))
+            .collect();
+        segments.sort_by_key(|(start, _)| *start);
+        segments
+    }
+
+    fn into_vec(self: Box<Self>) -> Option<Vec<i64>> {
+        None
+    }
+}
+
+/// Stretches of zeros at least this long split an image into separate runs
+const RUN_GAP: usize = 4;
+
+/// Most words held in one Vec, by `FlatMemory` or `Image::to_vec`
+pub const MAX_VEC_EXTENT: usize = 1 << 24;
+
+/// Sparse copy of memory: the runs of values it holds, each with its start address, and
+/// what kind of memory it came from. Long stretches of zeros between runs are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub kind: MemoryKind,
+    /// One past the highest address written
+    pub extent: usize,
+    /// (start address, values), in address order
+    pub runs: Vec<(usize, Vec<i64>)>,
+}
+
+impl Image {
+    pub fn of(mem: &dyn Memory) -> Image {
+        let mut runs: Vec<(usize, Vec<i64>)> = vec![];
+        for (start, values) in mem.segments() {
+            for (addr, x) in (start..).zip(values) {
+                if *x == 0 {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some((run_start, run)) if addr - (*run_start + run.len()) < RUN_GAP => {
+                        run.resize(addr - *run_start, 0);
+                        run.push(*x);
+                    },
+                    _ => runs.push((addr, vec![*x])),
+                }
+            }
+        }
+        Image{kind: mem.kind(), extent: mem.extent(), runs}
+    }
+
+    pub fn read(&self, addr: usize) -> i64 {
+        let i = self.runs.partition_point(|(start, _)| *start <= addr);
+        match i.checked_sub(1).map(|i| &self.runs[i]) {
+            Some((start, run)) => run.get(addr - start).copied().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Memory of the same kind as the image came from, holding the same values
+    pub fn load(&self) -> Box<dyn Memory> {
+        let mut mem = self.kind.load(vec![]);
+        for (start, run) in &self.runs {
+            for (addr, x) in (*start..).zip(run) {
+                mem.write(addr, *x);
+            }
+        }
+        // The highest address written may have been given a zero
+        if mem.extent() < self.extent {
+            mem.write(self.extent - 1, 0);
+        }
+        mem
+    }
+
+    /// Contents from address zero up to the extent, or None if that's more than
+    /// `MAX_VEC_EXTENT` words
+    pub fn to_vec(&self) -> Option<Vec<i64>> {
+        if self.extent > MAX_VEC_EXTENT {
+            return None;
+        }
+        let mut values = vec![0; self.extent];
+        for (start, run) in &self.runs {
+            values[*start..*start + run.len()].copy_from_slice(run);
+        }
+        Some(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::memory::*;
+    use crate::intcode::Executor;
+    use crate::intcode::fixtures::day9;
+
+    #[test]
+    fn test_sparse() {
+        for kind in &[MemoryKind::Flat, MemoryKind::Paged, MemoryKind::CopyOnWrite] {
+            let mut mem = kind.load(vec![1, 2, 3]);
+            assert_eq!(mem.read(1 << 40), 0);
+            assert_eq!(mem.extent(), 3);
+            mem.write(PAGE_SIZE + 1, 9);
+            assert_eq!(mem.read(PAGE_SIZE + 1), 9);
+            let image = Image::of(mem.as_ref());
+            assert_eq!(image.runs, vec![(0, vec![1, 2, 3]), (PAGE_SIZE + 1, vec![9])]);
+            assert_eq!(image.to_vec().unwrap().len(), PAGE_SIZE + 2);
+            assert_eq!(image.to_vec().unwrap()[..4], [1, 2, 3, 0]);
+        }
+
+        let mut paged = PagedMemory::from(vec![1, 2, 3]);
+        paged.write(1 << 40, 5);
+        assert_eq!(paged.read(1 << 40), 5);
+        assert_eq!(paged.pages_allocated(), 2);
+        assert!(Box::new(paged).into_vec().is_none());
+    }
+
+    #[test]
+    fn test_image() {
+        for kind in &[MemoryKind::Flat, MemoryKind::Paged, MemoryKind::CopyOnWrite] {
+            let mut mem = kind.load(vec![1, 0, 2, 0, 0, 0, 0, 3]);
+            if *kind != MemoryKind::Flat {
+                mem.write(1 << 40, 4);
+            }
+            mem.write(20, 0);
+            let image = Image::of(mem.as_ref());
+            assert_eq!(image.kind, *kind);
+            assert_eq!(&image.runs[..2], &[(0, vec![1, 0, 2]), (7, vec![3])]);
+            assert_eq!((image.read(2), image.read(3), image.read(7), image.read(100)), (2, 0, 3, 0));
+
+            let loaded = image.load();
+            assert_eq!(loaded.kind(), *kind);
+            assert_eq!(loaded.extent(), mem.extent());
+            assert_eq!(Image::of(loaded.as_ref()), image);
+        }
+
+        let mut huge = MemoryKind::Paged.load(vec![]);
+        huge.write(1 << 40, 1);
+        assert_eq!(Image::of(huge.as_ref()).to_vec(), None);
+    }
+
+    #[test]
+    fn test_copy_on_write() {
+        let original = CowMemory::from((0..1000).collect::<Vec<i64>>());
+        let mut copy = original.clone();
+        copy.write(10, -1);
+        assert_eq!(copy.read(10), -1);
+        assert_eq!(original.read(10), 10);
+        // Only the page that was written got copied
+        let shared = (0..1000 / PAGE_SIZE + 1).filter(|p| Arc::ptr_eq(&original.pages[p], &copy.pages[p])).count();
+        assert_eq!(shared, original.pages_allocated() - 1);
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let program = day9();
+        let mut results = vec![];
+        for kind in &[MemoryKind::Flat, MemoryKind::Paged, MemoryKind::CopyOnWrite] {
+            let mut m = Executor::with_memory(kind.load(program.clone()));
+            m.set_input(vec![1]);
+            m.run().unwrap();
+            results.push(m.output);
+        }
+        assert_eq!(results[0].len(), 1);
+        assert!(results.iter().all(|r| *r == results[0]));
+    }
+}
@@ -0,0 +1,350 @@
+//! Networks of Intcode machines wired output to input
+//!
+//! Machines are added with their program and any initial input, then links are declared from
+//! one machine's output to another's input. Values travel over a link in messages of `frame`
+//! values, so a message from one sender is never interleaved with another's.
+//!
+//! A network can be run cooperatively on the current thread, or with a thread per machine.
+//! Either way it runs until every machine has halted, or the remaining machines are all
+//! waiting for input with nothing left in flight.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+
+use crate::intcode::{Executor, IntcodeFault, StopConditions, StopReason};
+use crate::intcode::ports::{InputSource, OutputSink};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Link {
+    pub from: usize,
+    pub to: usize,
+    /// Number of values in each message
+    pub frame: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NetworkState {
+    /// Every machine halted
+    Halted,
+    /// Machines still running are all waiting on input that will never come
+    Deadlocked,
+    /// The given machine faulted, and the network was stopped
+    Faulted(usize, IntcodeFault),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkResult {
+    pub state: NetworkState,
+    /// Every value written by each machine
+    pub outputs: Vec<Vec<i64>>,
+}
+
+#[derive(Default)]
+pub struct Network {
+    programs: Vec<Vec<i64>>,
+    inputs: Vec<Vec<i64>>,
+    links: Vec<Link>,
+}
+
+impl Network {
+    pub fn new() -> Network {
+        Network::default()
+    }
+
+    /// Ring of machines all running `program`, each feeding the next and the last feeding
+    /// the first, starting with the given inputs
+    pub fn ring(program: &[i64], inputs: Vec<Vec<i64>>) -> Network {
+        let mut network = Network::new();
+        let n = inputs.len();
+        for input in inputs {
+            network.add_machine(program.to_vec(), input);
+        }
+        for i in 0..n {
+            network.connect(i, (i + 1) % n);
+        }
+        network
+    }
+
+    /// Add a machine, returning its index
+    pub fn add_machine(&mut self, program: Vec<i64>, initial_input: Vec<i64>) -> usize {
+        self.programs.push(program);
+        self.inputs.push(initial_input);
+        self.programs.len() - 1
+    }
+
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.connect_framed(from, to, 1);
+    }
+
+    /// Send output from `from` to `to` in messages of `frame` values
+    pub fn connect_framed(&mut self, from: usize, to: usize, frame: usize) {
+        assert!(from < self.programs.len() && to < self.programs.len(), "Link to unknown machine");
+        assert!(frame > 0, "Messages must hold at least one value");
+        self.links.push(Link{from, to, frame});
+    }
+
+    /// Run every machine in turn on the current thread
+    pub fn run(&self) -> NetworkResult {
+        let mut machines: Vec<Executor> = self.programs.iter().zip(&self.inputs).map(|(program, input)| {
+            let mut m = Executor::new(program.clone());
+            m.set_input(input.clone());
+            m
+        }).collect();
+        let mut buffers: Vec<Vec<i64>> = vec![vec![]; self.links.len()];
+        let mut outputs: Vec<Vec<i64>> = vec![vec![]; machines.len()];
+        let mut first_round = true;
+
+        loop {
+            let mut progress = false;
+            for i in 0..machines.len() {
+                if machines[i].halted() {
+                    continue;
+                }
+                progress |= first_round || !machines[i].input.is_empty();
+                let result = machines[i].run_until(&StopConditions::default());
+
+                for x in machines[i].output.drain(..).collect::<Vec<i64>>() {
+                    outputs[i].push(x);
+                    for (link, buffer) in self.links.iter().zip(buffers.iter_mut()) {
+                        if link.from != i {
+                            continue;
+                        }
+                        buffer.push(x);
+                        if buffer.len() == link.frame {
+                            for y in buffer.drain(..) {
+                                machines[link.to].push_input(y);
+                            }
+                        }
+                    }
+                }
+
+                match result {
+                    Ok(StopReason::Halted) => progress = true,
+                    Ok(_) => (),
+                    Err(fault) => return NetworkResult{state: NetworkState::Faulted(i, fault), outputs},
+                }
+            }
+            first_round = false;
+
+            if machines.iter().all(|m| m.halted()) {
+                return NetworkResult{state: NetworkState::Halted, outputs};
+            }
+            if !progress {
+                return NetworkResult{state: NetworkState::Deadlocked, outputs};
+            }
+        }
+    }
+
+    /// Run each machine on its own thread, passing messages over channels
+    pub fn run_threaded(&self) -> NetworkResult {
+        let n = self.programs.len();
+        let monitor = Arc::new(Monitor{
+            state: Mutex::new(MonitorState{running: n, waiting: 0, in_flight: 0, finished: vec![false; n], stopped: false}),
+            wake: Condvar::new(),
+        });
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..n).map(|_| channel::<Vec<i64>>()).unzip();
+
+        let mut handles = vec![];
+        for (id, receiver) in receivers.into_iter().enumerate() {
+            let links: Vec<(Link, Sender<Vec<i64>>, Vec<i64>)> = self.links.iter()
+                .filter(|link| link.from == id)
+                .map(|link| (*link, senders[link.to].clone(), vec![]))
+                .collect();
+            let input = NodeInput{pending: self.inputs[id].iter().copied().collect(), receiver, monitor: monitor.clone()};
+            let output = NodeOutput{links, written: vec![], monitor: monitor.clone()};
+            let program = self.programs[id].clone();
+            let monitor = monitor.clone();
+
+            handles.push(thread::spawn(move || {
+                let mut m = Executor::with_io(program, input, output);
+                let result = m.run_until(&StopConditions::default());
+                monitor.finished(id, result.is_err(), &m.input.receiver);
+                (result, m.output.written)
+            }));
+        }
+        drop(senders);
+
+        let mut outputs = vec![];
+        let mut state = NetworkState::Halted;
+        for (id, handle) in handles.into_iter().enumerate() {
+            let (result, written) = handle.join().unwrap();
+            match (result, state) {
+                (Err(fault), NetworkState::Halted) | (Err(fault), NetworkState::Deadlocked) => state = NetworkState::Faulted(id, fault),
+                (Ok(StopReason::NeedsInput), NetworkState::Halted) => state = NetworkState::Deadlocked,
+                _ => (),
+            }
+            outputs.push(written);
+        }
+        NetworkResult{state, outputs}
+    }
+}
+
+/// Bookkeeping shared by the threads of a network, to work out when nothing more can happen
+struct Monitor {
+    state: Mutex<MonitorState>,
+    wake: Condvar,
+}
+
+struct MonitorState {
+    /// Machines that haven't finished
+    running: usize,
+    /// Machines blocked waiting for a message
+    waiting: usize,
+    /// Messages sent but not yet received
+    in_flight: usize,
+    /// Machines which will never read another message
+    finished: Vec<bool>,
+    /// Set once the network is stuck (or a machine faults), releasing everyone still waiting
+    stopped: bool,
+}
+
+impl MonitorState {
+    fn check_stuck(&mut self) -> bool {
+        if self.running > 0 && self.waiting == self.running && self.in_flight == 0 {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+}
+
+impl Monitor {
+    fn send(&self, to: usize, sender: &Sender<Vec<i64>>, message: Vec<i64>) {
+        let mut state = self.state.lock().unwrap();
+        // Nobody will ever read messages sent to a finished machine
+        if !state.finished[to] && sender.send(message).is_ok() {
+            state.in_flight += 1;
+            self.wake.notify_all();
+        }
+    }
+
+    fn receive(&self, receiver: &Receiver<Vec<i64>>) -> Option<Vec<i64>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Ok(message) = receiver.try_recv() {
+                state.in_flight -= 1;
+                return Some(message);
+            }
+            state.waiting += 1;
+            if state.check_stuck() {
+                state.waiting -= 1;
+                self.wake.notify_all();
+                return None;
+            }
+            state = self.wake.wait(state).unwrap();
+            state.waiting -= 1;
+        }
+    }
+
+    /// Called when a machine's thread is done. Machines that faulted stop the whole network.
+    fn finished(&self, id: usize, faulted: bool, receiver: &Receiver<Vec<i64>>) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        state.finished[id] = true;
+        // Anything left unread is never going to be
+        while receiver.try_recv().is_ok() {
+            state.in_flight -= 1;
+        }
+        if faulted {
+            state.stopped = true;
+        }
+        state.check_stuck();
+        self.wake.notify_all();
+    }
+}
+
+struct NodeInput {
+    pending: VecDeque<i64>,
+    receiver: Receiver<Vec<i64>>,
+    monitor: Arc<Monitor>,
+}
+
+impl InputSource for NodeInput {
+    fn read(&mut self) -> Option<i64> {
+        if self.pending.is_empty() {
+            let message = self.monitor.receive(&self.receiver)?;
+            self.pending.extend(message);
+        }
+        self.pending.pop_front()
+    }
+}
+
+struct NodeOutput {
+    links: Vec<(Link, Sender<Vec<i64>>, Vec<i64>)>,
+    written: Vec<i64>,
+    monitor: Arc<Monitor>,
+}
+
+impl OutputSink for NodeOutput {
+    fn write(&mut self, x: i64) {
+        self.written.push(x);
+        for (link, sender, buffer) in &mut self.links {
+            buffer.push(x);
+            if buffer.len() == link.frame {
+                self.monitor.send(link.to, sender, std::mem::take(buffer));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::network::*;
+    use crate::intcode::asm::assemble;
+
+    // Day 7 part 2 example, which should settle on 139629729
+    fn amplifier() -> Vec<i64> {
+        vec![3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5]
+    }
+
+    fn echo() -> Vec<i64> {
+        assemble("
+            loop:   in    x
+                    out   x
+                    jt    #1, #loop
+            x:      data  0
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_ring() {
+        let inputs = vec![vec![9, 0], vec![8], vec![7], vec![6], vec![5]];
+        let result = Network::ring(&amplifier(), inputs.clone()).run();
+        assert_eq!(result.state, NetworkState::Halted);
+        assert_eq!(result.outputs[4].last(), Some(&139629729));
+
+        let threaded = Network::ring(&amplifier(), inputs).run_threaded();
+        assert_eq!(threaded, result);
+    }
+
+    #[test]
+    fn test_deadlock_and_framing() {
+        for threaded in &[false, true] {
+            let mut network = Network::new();
+            let source = network.add_machine(vec![104, 1, 104, 2, 104, 3, 99], vec![]);
+            let a = network.add_machine(echo(), vec![]);
+            let b = network.add_machine(echo(), vec![10]);
+            network.connect_framed(source, a, 2);
+            network.connect(a, b);
+
+            let result = if *threaded { network.run_threaded() } else { network.run() };
+            assert_eq!(result.state, NetworkState::Deadlocked);
+            // The final 3 never makes up a whole message
+            assert_eq!(result.outputs[a], vec![1, 2]);
+            assert_eq!(result.outputs[b], vec![10, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn test_fault() {
+        let mut network = Network::new();
+        let bad = network.add_machine(vec![104, 7, 42], vec![]);
+        let a = network.add_machine(echo(), vec![]);
+        network.connect(bad, a);
+        for result in &[network.run(), network.run_threaded()] {
+            assert_eq!(result.state, NetworkState::Faulted(bad, IntcodeFault::UnknownOpcode{pc: 2, instr: 42}));
+            assert_eq!(result.outputs[bad], vec![7]);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Input and output channels for `Executor`
+//!
+//! An input source hands values to the program one at a time, returning None when nothing is
+//! available. That leaves the machine waiting on its input instruction (see
+//! `StopReason::NeedsInput`), so it can be resumed later. Output sinks take each value the
+//! program writes.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+use crate::intcode::ascii::{encode_line, render_value};
+
+pub trait InputSource {
+    /// Next input value, or None if there isn't one available
+    fn read(&mut self) -> Option<i64>;
+}
+
+pub trait OutputSink {
+    fn write(&mut self, x: i64);
+}
+
+impl InputSource for VecDeque<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl OutputSink for VecDeque<i64> {
+    fn write(&mut self, x: i64) {
+        self.push_back(x);
+    }
+}
+
+impl OutputSink for Vec<i64> {
+    fn write(&mut self, x: i64) {
+        self.push(x);
+    }
+}
+
+/// Input from a closure
+#[derive(Clone)]
+pub struct InputFn<F>(pub F);
+
+impl<F: FnMut() -> Option<i64>> InputSource for InputFn<F> {
+    fn read(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+/// Output to a closure
+#[derive(Clone)]
+pub struct OutputFn<F>(pub F);
+
+impl<F: FnMut(i64)> OutputSink for OutputFn<F> {
+    fn write(&mut self, x: i64) {
+        (self.0)(x)
+    }
+}
+
+/// Blocks until a value is sent, and runs dry once every sender is gone
+impl InputSource for Receiver<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Values sent after the receiver hangs up are dropped
+impl OutputSink for Sender<i64> {
+    fn write(&mut self, x: i64) {
+        let _ = self.send(x);
+    }
+}
+
+/// Reads lines from stdin, feeding them to the program as ASCII codes, newline included
+#[derive(Default)]
+pub struct AsciiStdin {
+    pending: VecDeque<i64>,
+}
+
+impl InputSource for AsciiStdin {
+    fn read(&mut self) -> Option<i64> {
+        if self.pending.is_empty() {
+            // Make sure any prompt the program wrote is visible
+            io::stdout().flush().ok()?;
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => (),
+            }
+            self.pending.extend(encode_line(line.trim_end_matches(['\r', '\n'])));
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Prints values in the ASCII range as characters, and anything else as a number on its own line
+#[derive(Default)]
+pub struct AsciiStdout;
+
+impl OutputSink for AsciiStdout {
+    fn write(&mut self, x: i64) {
+        print!("{}", render_value(x));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use crate::intcode::ports::*;
+    use crate::intcode::{Executor, StopConditions, StopReason};
+    use crate::intcode::asm::assemble;
+
+    // Outputs the sum of each pair of inputs
+    fn adder() -> Vec<i64> {
+        assemble("
+            loop:   in    a
+                    in    b
+                    add   a, b, a
+                    out   a
+                    jt    #1, #loop
+            a:      data  0
+            b:      data  0
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut next = 0;
+        let mut sums = vec![];
+        let mut m = Executor::with_io(adder(), InputFn(|| {
+            next += 1;
+            if next <= 4 { Some(next) } else { None }
+        }), OutputFn(|x| sums.push(x)));
+        assert_eq!(m.run_until(&StopConditions::default()), Ok(StopReason::NeedsInput));
+        drop(m);
+        assert_eq!(sums, vec![3, 7]);
+    }
+
+    #[test]
+    fn test_channels() {
+        let (to_machine, input) = channel();
+        let (output, from_machine) = channel();
+        let handle = thread::spawn(move || {
+            let mut m = Executor::with_io(adder(), input, output);
+            m.run_until(&StopConditions::default())
+        });
+        to_machine.send(20).unwrap();
+        to_machine.send(22).unwrap();
+        assert_eq!(from_machine.recv(), Ok(42));
+        to_machine.send(1).unwrap();
+        drop(to_machine);
+        assert_eq!(handle.join().unwrap(), Ok(StopReason::NeedsInput));
+        assert!(from_machine.recv().is_err());
+    }
+
+    #[test]
+    fn test_queue_output() {
+        let mut m = Executor::with_io(adder(), VecDeque::from(vec![1, 2, 3, 4]), VecDeque::new());
+        m.run_until(&StopConditions::default()).unwrap();
+        assert_eq!(m.output.pop_front(), Some(3));
+        assert_eq!(m.output.pop_front(), Some(7));
+    }
+}
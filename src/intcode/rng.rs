@@ -0,0 +1,47 @@
+//! Pseudo-random numbers
+//!
+//! Small, fast, and reproducible from a seed, which is all the fuzzer and the `Random` device
+//! need. Not for anything that has to be unpredictable.
+
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift gets stuck at zero
+        Rng((seed ^ 0x9e37_79b9_7f4a_7c15).max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        // xorshift64*
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Uniform in lo..=hi
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.next_u64() % (hi - lo + 1) as u64) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::rng::*;
+
+    #[test]
+    fn test_rng() {
+        let mut rng = Rng::new(0);
+        let values: Vec<u64> = (0..100).map(|_| rng.next_u64()).collect();
+        assert!(values.iter().all(|x| *x != 0));
+        assert_eq!(values, (0..100).scan(Rng::new(0), |rng, _| Some(rng.next_u64())).collect::<Vec<_>>());
+        assert!((0..1000).all(|_| (-3..=3).contains(&rng.range(-3, 3))));
+        assert!((0..1000).all(|_| rng.below(7) < 7));
+    }
+}
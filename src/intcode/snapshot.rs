@@ -0,0 +1,369 @@
+//! Saving and restoring machine state
+//!
+//! A snapshot holds everything needed to carry on running a machine: PC, relative base,
+//! memory, pending input, output so far and whether it has halted. Watchpoints and traces
+//! are debugging aids, and aren't included.
+//!
+//! Memory is kept as an `Image`, so only the runs of values actually held are stored, and a
+//! machine restored from a snapshot gets back the same kind of memory it had.
+//!
+//! There are two formats. The binary one stores each number as a zigzag varint, so the
+//! mostly-small values in Intcode memory take a byte or two each. The text one is meant for
+//! reading, and for attaching to bug reports. It has a `mem` line for each run, giving its
+//! start address:
+//!
+//! ```text
+//! intcode snapshot
+//! pc: 2
+//! base: 0
+//! halted: false
+//! input: 5,6
+//! output: 1
+//! memory: flat
+//! extent: 13
+//! mem 0: 3,12,4,12,1001,12,-1,12,1005,12,2,99
+//! ```
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::intcode::Executor;
+use crate::intcode::memory::{Image, MAX_VEC_EXTENT, MemoryKind};
+
+const MAGIC: &[u8] = b"ICSNAP\x02";
+const TEXT_HEADER: &str = "intcode snapshot";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Binary,
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub pc: u32,
+    pub base_reg: i64,
+    pub halted: bool,
+    pub mem: Image,
+    pub input: Vec<i64>,
+    pub output: Vec<i64>,
+}
+
+fn put_varint(bytes: &mut Vec<u8>, x: i64) {
+    let mut zigzag = ((x << 1) ^ (x >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn get_varint<I: Iterator<Item = u8>>(bytes: &mut I) -> Result<i64> {
+    let mut zigzag = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes.next().ok_or_else(|| anyhow!("Snapshot ends mid-number"))?;
+        zigzag |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+    bail!("Snapshot number is too long")
+}
+
+fn get_usize<I: Iterator<Item = u8>>(bytes: &mut I, what: &str) -> Result<usize> {
+    let x = get_varint(bytes)?;
+    usize::try_from(x).map_err(|_| anyhow!("Bad snapshot {} {}", what, x))
+}
+
+fn put_list(bytes: &mut Vec<u8>, values: &[i64]) {
+    put_varint(bytes, values.len() as i64);
+    for x in values {
+        put_varint(bytes, *x);
+    }
+}
+
+fn get_list<I: Iterator<Item = u8>>(bytes: &mut I) -> Result<Vec<i64>> {
+    let len = get_varint(bytes)?;
+    (0..len).map(|_| get_varint(bytes)).collect()
+}
+
+fn join(values: &[i64]) -> String {
+    values.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(",")
+}
+
+const KINDS: [(MemoryKind, &str); 3] = [(MemoryKind::Flat, "flat"), (MemoryKind::Paged, "paged"), (MemoryKind::CopyOnWrite, "cow")];
+
+fn kind_name(kind: MemoryKind) -> &'static str {
+    KINDS.iter().find(|(k, _)| *k == kind).unwrap().1
+}
+
+fn kind_from_name(name: &str) -> Result<MemoryKind> {
+    KINDS.iter().find(|(_, n)| *n == name).map(|(k, _)| *k).ok_or_else(|| anyhow!("Unknown memory kind '{}'", name))
+}
+
+fn kind_from_index(index: usize) -> Result<MemoryKind> {
+    KINDS.get(index).map(|(k, _)| *k).ok_or_else(|| anyhow!("Unknown memory kind {}", index))
+}
+
+/// Where the last run ends, checking that they're in order and don't overlap
+fn runs_end(runs: &[(usize, Vec<i64>)]) -> Result<usize> {
+    let mut end = 0;
+    for (start, run) in runs {
+        if *start < end {
+            bail!("Snapshot memory runs overlap or are out of order at {}", start);
+        }
+        end = start.checked_add(run.len()).ok_or_else(|| anyhow!("Snapshot memory run at {} is too long", start))?;
+    }
+    Ok(end)
+}
+
+/// Check the image can be loaded: that the runs fit in the extent, and flat memory isn't
+/// bigger than it can be
+fn check_image(image: &Image) -> Result<()> {
+    if runs_end(&image.runs)? > image.extent {
+        bail!("Snapshot memory runs go past its extent of {}", image.extent);
+    }
+    if image.kind == MemoryKind::Flat && image.extent > MAX_VEC_EXTENT {
+        bail!("Snapshot extent {} is too big for flat memory", image.extent);
+    }
+    Ok(())
+}
+
+fn put_image(bytes: &mut Vec<u8>, image: &Image) {
+    put_varint(bytes, KINDS.iter().position(|(k, _)| *k == image.kind).unwrap() as i64);
+    put_varint(bytes, image.extent as i64);
+    put_varint(bytes, image.runs.len() as i64);
+    for (start, run) in &image.runs {
+        put_varint(bytes, *start as i64);
+        put_list(bytes, run);
+    }
+}
+
+fn get_image<I: Iterator<Item = u8>>(bytes: &mut I) -> Result<Image> {
+    let kind = kind_from_index(get_usize(bytes, "memory kind")?)?;
+    let extent = get_usize(bytes, "extent")?;
+    let runs = (0..get_usize(bytes, "run count")?).map(|_| Ok((get_usize(bytes, "run start")?, get_list(bytes)?))).collect::<Result<_>>()?;
+    let image = Image{kind, extent, runs};
+    check_image(&image)?;
+    Ok(image)
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        put_varint(&mut bytes, self.pc as i64);
+        put_varint(&mut bytes, self.base_reg);
+        put_varint(&mut bytes, self.halted as i64);
+        put_image(&mut bytes, &self.mem);
+        put_list(&mut bytes, &self.input);
+        put_list(&mut bytes, &self.output);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot> {
+        if !bytes.starts_with(MAGIC) {
+            bail!("Not a binary snapshot");
+        }
+        let mut bytes = bytes[MAGIC.len()..].iter().copied();
+        let snapshot = Snapshot{
+            pc: u32::try_from(get_usize(&mut bytes, "pc")?).map_err(|_| anyhow!("Bad snapshot pc"))?,
+            base_reg: get_varint(&mut bytes)?,
+            halted: get_varint(&mut bytes)? != 0,
+            mem: get_image(&mut bytes)?,
+            input: get_list(&mut bytes)?,
+            output: get_list(&mut bytes)?,
+        };
+        if bytes.next().is_some() {
+            bail!("Unexpected data at end of snapshot");
+        }
+        Ok(snapshot)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\npc: {}\nbase: {}\nhalted: {}\ninput: {}\noutput: {}\nmemory: {}\nextent: {}\n",
+                               TEXT_HEADER, self.pc, self.base_reg, self.halted, join(&self.input), join(&self.output),
+                               kind_name(self.mem.kind), self.mem.extent);
+        for (start, run) in &self.mem.runs {
+            text += &format!("mem {}: {}\n", start, join(run));
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Snapshot> {
+        let mut lines = text.lines();
+        if lines.next().map(|l| l.trim()) != Some(TEXT_HEADER) {
+            bail!("Not a text snapshot");
+        }
+        let mem = Image{kind: MemoryKind::Flat, extent: 0, runs: vec![]};
+        let mut snapshot = Snapshot{pc: 0, base_reg: 0, halted: false, mem, input: vec![], output: vec![]};
+        let mut extent = None;
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            let (key, value) = line.split_once(':').ok_or_else(|| anyhow!("Bad snapshot line '{}'", line))?;
+            let value = value.trim();
+            let list = || -> Result<Vec<i64>> {
+                value.split(',').filter(|v| !v.trim().is_empty()).map(|v| Ok(v.trim().parse::<i64>()?)).collect()
+            };
+            match key.trim() {
+                "pc" => snapshot.pc = value.parse()?,
+                "base" => snapshot.base_reg = value.parse()?,
+                "halted" => snapshot.halted = value.parse()?,
+                "input" => snapshot.input = list()?,
+                "output" => snapshot.output = list()?,
+                "memory" => snapshot.mem.kind = kind_from_name(value)?,
+                "extent" => extent = Some(value.parse()?),
+                key if key.starts_with("mem ") => {
+                    let start: usize = key["mem ".len()..].trim().parse()?;
+                    snapshot.mem.runs.push((start, list()?));
+                },
+                other => bail!("Unknown snapshot field '{}'", other),
+            }
+        }
+        let end = runs_end(&snapshot.mem.runs)?;
+        snapshot.mem.extent = extent.unwrap_or(end).max(end);
+        check_image(&snapshot.mem)?;
+        Ok(snapshot)
+    }
+}
+
+impl Executor {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot{
+            pc: self.pc,
+            base_reg: self.base_reg,
+            halted: self.halted,
+            mem: self.image(),
+            input: self.input.iter().copied().collect(),
+            output: self.output.clone(),
+        }
+    }
+
+    pub fn from_snapshot(snapshot: Snapshot) -> Executor {
+        let mut m = Executor::with_memory(snapshot.mem.load());
+        m.pc = snapshot.pc;
+        m.base_reg = snapshot.base_reg;
+        m.halted = snapshot.halted;
+        m.input = VecDeque::from(snapshot.input);
+        m.output = snapshot.output;
+        m
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: Format) -> Result<()> {
+        let snapshot = self.snapshot();
+        match format {
+            Format::Binary => fs::write(path, snapshot.to_bytes())?,
+            Format::Text => fs::write(path, snapshot.to_text())?,
+        }
+        Ok(())
+    }
+
+    /// Load a machine saved with `save`, in either format
+    pub fn restore<P: AsRef<Path>>(path: P) -> Result<Executor> {
+        let bytes = fs::read(path)?;
+        let snapshot = if bytes.starts_with(MAGIC) {
+            Snapshot::from_bytes(&bytes)?
+        } else {
+            Snapshot::from_text(&String::from_utf8(bytes)?)?
+        };
+        Ok(Executor::from_snapshot(snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use crate::intcode::snapshot::*;
+    use crate::intcode::StopConditions;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::memory::MemoryKind;
+
+    fn running_machine() -> Executor {
+        let program = assemble("
+                    rbo   #-300
+                    in    count
+            loop:   out   count
+                    add   count, #-1, count
+                    jt    count, #loop
+                    halt
+            count:  data  0
+        ").unwrap();
+        let mut m = Executor::new(program);
+        m.set_input(vec![3, 1234567890123, -9]);
+        m.outputs().next();
+        m
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let m = running_machine();
+        let snapshot = m.snapshot();
+        assert_eq!(snapshot.pc, 6);
+        assert_eq!(snapshot.base_reg, -300);
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+        assert_eq!(Snapshot::from_text(&snapshot.to_text()).unwrap(), snapshot);
+        assert!(snapshot.to_bytes().len() < 2 * snapshot.mem.extent + 20);
+        assert!(Snapshot::from_bytes(&snapshot.to_bytes()[..20]).is_err());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let snapshot = running_machine().snapshot();
+        let mut bytes = snapshot.to_bytes();
+        // A PC of -3, as a zigzag varint
+        bytes[MAGIC.len()] = 5;
+        assert!(Snapshot::from_bytes(&bytes).is_err());
+
+        let mut too_big = snapshot.clone();
+        too_big.mem.extent = MAX_VEC_EXTENT + 1;
+        assert!(Snapshot::from_bytes(&too_big.to_bytes()).is_err());
+        assert!(Snapshot::from_text(&too_big.to_text()).is_err());
+        too_big.mem.kind = MemoryKind::Paged;
+        assert!(Snapshot::from_bytes(&too_big.to_bytes()).is_ok());
+
+        let mut overlapping = snapshot.clone();
+        overlapping.mem.runs.push((1, vec![1]));
+        assert!(Snapshot::from_bytes(&overlapping.to_bytes()).is_err());
+        assert!(Snapshot::from_text(&overlapping.to_text()).is_err());
+        let mut short = snapshot;
+        short.mem.extent = 1;
+        assert!(Snapshot::from_bytes(&short.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_save_and_resume() {
+        for (format, name) in &[(Format::Binary, "aoc2019-snapshot.bin"), (Format::Text, "aoc2019-snapshot.txt")] {
+            let path = env::temp_dir().join(name);
+            let mut m = running_machine();
+            m.save(&path, *format).unwrap();
+            let mut restored = Executor::restore(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            m.run_until(&StopConditions::default()).unwrap();
+            restored.run_until(&StopConditions::default()).unwrap();
+            assert_eq!(restored.output, vec![3, 2, 1]);
+            assert_eq!(restored.snapshot(), m.snapshot());
+        }
+    }
+
+    #[test]
+    fn test_sparse_memory() {
+        // Writes 7 to 2^40, which flat memory couldn't hold
+        let mut m = Executor::with_memory(MemoryKind::Paged.load(vec![1101, 7, 0, 1 << 40, 99]));
+        m.run().unwrap();
+        let snapshot = m.snapshot();
+        assert_eq!(snapshot.mem.runs, vec![(0, vec![1101, 7, 0, 1 << 40, 99]), (1 << 40, vec![7])]);
+        assert!(snapshot.to_bytes().len() < 64);
+        assert_eq!(Snapshot::from_text(&snapshot.to_text()).unwrap(), snapshot);
+
+        let restored = Executor::from_snapshot(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap());
+        assert_eq!(restored.memory().kind(), MemoryKind::Paged);
+        assert_eq!((restored.peek(1 << 40), restored.memory().extent()), (7, (1 << 40) + 1));
+    }
+}
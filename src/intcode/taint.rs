@@ -0,0 +1,175 @@
+//! Taint tracking, to find out which inputs a value depends on
+//!
+//! Turn on with `Executor::enable_taint`, giving any memory cells to tag as sources, such as
+//! patched parameters. Input values are tagged as they're read. Every memory cell, output
+//! value and the relative base then carries the set of sources it was computed from.
+//!
+//! Only data flow is followed. A value depends on everything read to produce it: operand
+//! values, the words they were addressed by, and the relative base for relative operands.
+//! Taking one side of a jump or the other doesn't make anything depend on its condition, so a
+//! program which copies an input by testing it bit by bit will lose the taint.
+//!
+//! As with traces, stepping back with history doesn't undo taint.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Source {
+    /// The nth value read from input, counting from 0
+    Input(usize),
+    /// A memory cell tagged when tracking started
+    Memory(usize),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Input(n) => write!(f, "input {}", n),
+            Source::Memory(addr) => write!(f, "mem[{}]", addr),
+        }
+    }
+}
+
+pub type Labels = BTreeSet<Source>;
+
+#[derive(Debug, Clone, Default)]
+pub struct Taint {
+    cells: HashMap<usize, Labels>,
+    base: Labels,
+    outputs: Vec<Labels>,
+    inputs_read: usize,
+    /// Everything read so far by the current instruction
+    current: Labels,
+}
+
+impl Taint {
+    /// Tracking with each of `tagged` as a source
+    pub fn new(tagged: &[usize]) -> Taint {
+        let mut taint = Taint::default();
+        for addr in tagged {
+            taint.cells.insert(*addr, std::iter::once(Source::Memory(*addr)).collect());
+        }
+        taint
+    }
+
+    /// Sources the memory cell at `addr` depends on
+    pub fn memory(&self, addr: usize) -> Labels {
+        self.cells.get(&addr).cloned().unwrap_or_default()
+    }
+
+    /// Addresses of all cells that depend on some source
+    pub fn tainted_cells(&self) -> BTreeSet<usize> {
+        self.cells.keys().copied().collect()
+    }
+
+    /// Sources for each value output so far
+    pub fn outputs(&self) -> &[Labels] {
+        &self.outputs
+    }
+
+    /// Sources the relative base depends on
+    pub fn base(&self) -> &Labels {
+        &self.base
+    }
+
+    pub(crate) fn begin(&mut self) {
+        self.current.clear();
+    }
+
+    pub(crate) fn read(&mut self, addr: usize) {
+        if let Some(labels) = self.cells.get(&addr) {
+            self.current.extend(labels);
+        }
+    }
+
+    pub(crate) fn read_base(&mut self) {
+        self.current.extend(&self.base);
+    }
+
+    pub(crate) fn input(&mut self) {
+        self.current.insert(Source::Input(self.inputs_read));
+        self.inputs_read += 1;
+    }
+
+    pub(crate) fn write(&mut self, addr: usize) {
+        if self.current.is_empty() {
+            self.cells.remove(&addr);
+        } else {
+            self.cells.insert(addr, self.current.clone());
+        }
+    }
+
+    pub(crate) fn output(&mut self) {
+        self.outputs.push(self.current.clone());
+    }
+
+    /// The relative base was adjusted by what the current instruction read
+    pub(crate) fn rebase(&mut self) {
+        self.base.extend(&self.current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::Executor;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::taint::*;
+
+    fn labels(sources: &[Source]) -> Labels {
+        sources.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_data_flow() {
+        let program = assemble("
+                    in    a
+                    in    b
+                    mult  a, #3, a
+                    add   a, k, c
+                    out   c
+                    out   b
+                    add   #1, #2, k
+                    out   k
+                    lt    a, b, c
+                    halt
+            a:      data  0
+            b:      data  0
+            c:      data  0
+            k:      data  10
+        ").unwrap();
+        let k = program.len() - 1;
+        let mut m = Executor::new(program);
+        m.enable_taint(&[k]);
+        m.set_input(vec![4, 5]);
+        m.run().unwrap();
+        assert_eq!(m.output, vec![22, 5, 3]);
+
+        let taint = m.taint().unwrap();
+        assert_eq!(taint.outputs(), &[
+            labels(&[Source::Input(0), Source::Memory(k)]),
+            labels(&[Source::Input(1)]),
+            labels(&[]),
+        ]);
+        assert_eq!(taint.memory(k - 1), labels(&[Source::Input(0), Source::Input(1)]));
+        assert_eq!(taint.tainted_cells().len(), 3);
+    }
+
+    #[test]
+    fn test_addresses() {
+        // Patching the address of an operand taints the result, as does the relative base
+        let program = assemble("
+                    add   0, 0, 100
+                    rbo   200
+                    out   rel[0]
+                    halt
+        ").unwrap();
+        let mut m = Executor::new(program);
+        m.enable_taint(&[1, 200]);
+        m.run().unwrap();
+        let taint = m.taint().unwrap();
+        assert_eq!(taint.memory(100), labels(&[Source::Memory(1)]));
+        assert_eq!(taint.base(), &labels(&[Source::Memory(200)]));
+        assert_eq!(taint.outputs()[0], labels(&[Source::Memory(200)]));
+    }
+}
@@ -0,0 +1,201 @@
+//! Execution tracing and profiling
+//!
+//! Turn on with `Executor::enable_trace`. Every executed instruction updates a `Profile`, and
+//! if asked for, a full `TraceEntry` is kept too. Traces can be written out as CSV or JSON
+//! lines for looking at elsewhere.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::intcode::Instruction;
+
+/// One executed instruction, with operands as resolved at the time
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub pc: u32,
+    pub instruction: Instruction,
+    /// (address, value) for each memory write made by the instruction
+    pub writes: Vec<(i64, i64)>,
+}
+
+/// Hit counts for a basic block, i.e. a run of instructions ending in a jump
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BlockStats {
+    pub start: u32,
+    pub entries: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub cycles: u64,
+    pub pc_hits: HashMap<u32, u64>,
+    pub opcode_hits: HashMap<&'static str, u64>,
+    /// Basic blocks as seen at run time, keyed by the PC they were entered at
+    pub blocks: HashMap<u32, BlockStats>,
+    pub max_addr: i64,
+}
+
+impl Profile {
+    /// The `n` blocks that took up the most cycles
+    pub fn hottest_blocks(&self, n: usize) -> Vec<BlockStats> {
+        let mut blocks: Vec<BlockStats> = self.blocks.values().copied().collect();
+        blocks.sort_by_key(|b| (std::cmp::Reverse(b.cycles), b.start));
+        blocks.truncate(n);
+        blocks
+    }
+
+    /// Per-PC hit counts as CSV, in address order
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut hits: Vec<(&u32, &u64)> = self.pc_hits.iter().collect();
+        hits.sort();
+        writeln!(w, "pc,hits")?;
+        for (pc, count) in hits {
+            writeln!(w, "{},{}", pc, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Cycles: {}", self.cycles)?;
+        writeln!(f, "Max address: {}", self.max_addr)?;
+        let mut opcodes: Vec<(&&str, &u64)> = self.opcode_hits.iter().collect();
+        opcodes.sort_by_key(|(name, count)| (std::cmp::Reverse(**count), **name));
+        writeln!(f, "Opcodes:")?;
+        for (name, count) in opcodes {
+            writeln!(f, "  {:<6}{}", name, count)?;
+        }
+        writeln!(f, "Hottest blocks:")?;
+        for block in self.hottest_blocks(10) {
+            writeln!(f, "  {:>5}: {} cycles in {} entries", block.start, block.cycles, block.entries)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Tracer {
+    pub profile: Profile,
+    /// Only collected if asked for, since it gets big quickly
+    pub entries: Option<Vec<TraceEntry>>,
+    writes: Vec<(i64, i64)>,
+    block: Option<BlockStats>,
+}
+
+impl Tracer {
+    pub fn new(keep_entries: bool) -> Tracer {
+        Tracer{entries: if keep_entries { Some(vec![]) } else { None }, ..Default::default()}
+    }
+
+    pub(crate) fn touch(&mut self, addr: i64) {
+        self.profile.max_addr = self.profile.max_addr.max(addr);
+    }
+
+    pub(crate) fn write(&mut self, addr: i64, value: i64) {
+        self.touch(addr);
+        self.writes.push((addr, value));
+    }
+
+    /// Forget about writes from an instruction which didn't complete
+    pub(crate) fn abandon(&mut self) {
+        self.writes.clear();
+    }
+
+    /// Record an instruction executed at `pc`, which moved the PC on to `next_pc`
+    pub(crate) fn record(&mut self, pc: u32, instruction: Instruction, next_pc: u32) {
+        let profile = &mut self.profile;
+        *profile.pc_hits.entry(pc).or_insert(0) += 1;
+        *profile.opcode_hits.entry(instruction.mnemonic()).or_insert(0) += 1;
+        profile.max_addr = profile.max_addr.max((pc as usize + instruction.len() - 1) as i64);
+
+        let block = self.block.get_or_insert(BlockStats{start: pc, entries: 1, cycles: 0});
+        block.cycles += 1;
+        let ends_block = match instruction {
+            Instruction::JmpTrue(_, _) | Instruction::JmpFalse(_, _) | Instruction::Stop => true,
+            _ => next_pc != pc + instruction.len() as u32,
+        };
+        if ends_block {
+            let stats = profile.blocks.entry(block.start).or_insert(BlockStats{start: block.start, entries: 0, cycles: 0});
+            stats.entries += 1;
+            stats.cycles += block.cycles;
+            self.block = None;
+        }
+
+        let writes = std::mem::take(&mut self.writes);
+        if let Some(entries) = &mut self.entries {
+            entries.push(TraceEntry{cycle: profile.cycles, pc, instruction, writes});
+        }
+        profile.cycles += 1;
+    }
+
+    fn entries(&self) -> &[TraceEntry] {
+        self.entries.as_deref().unwrap_or(&[])
+    }
+
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "cycle,pc,op,operands,writes")?;
+        for e in self.entries() {
+            let operands: Vec<String> = e.instruction.operands().iter().map(|x| x.to_string()).collect();
+            let writes: Vec<String> = e.writes.iter().map(|(addr, value)| format!("{}={}", addr, value)).collect();
+            writeln!(w, "{},{},{},{},{}", e.cycle, e.pc, e.instruction.mnemonic(), operands.join(" "), writes.join(" "))?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json_lines<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for e in self.entries() {
+            let writes: Vec<String> = e.writes.iter().map(|(addr, value)| format!("[{},{}]", addr, value)).collect();
+            writeln!(w, "{{\"cycle\":{},\"pc\":{},\"op\":\"{}\",\"operands\":{:?},\"writes\":[{}]}}",
+                     e.cycle, e.pc, e.instruction.mnemonic(), e.instruction.operands(), writes.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::Executor;
+    use crate::intcode::fixtures::countdown;
+
+    #[test]
+    fn test_profile() {
+        let mut m = Executor::new(countdown());
+        m.set_input(vec![3]);
+        m.enable_trace(false);
+        m.run().unwrap();
+        let tracer = m.trace().unwrap();
+        let profile = &tracer.profile;
+        assert!(tracer.entries.is_none());
+        assert_eq!(profile.cycles, 1 + 3 * 3 + 1);
+        assert_eq!(profile.pc_hits[&2], 3);
+        assert_eq!(profile.opcode_hits["jt"], 3);
+        assert_eq!(profile.max_addr, 12);
+        let hottest = profile.hottest_blocks(1)[0];
+        assert_eq!((hottest.start, hottest.entries, hottest.cycles), (2, 2, 6));
+    }
+
+    #[test]
+    fn test_export() {
+        let mut m = Executor::new(countdown());
+        m.set_input(vec![1]);
+        m.enable_trace(true);
+        m.run().unwrap();
+        let tracer = m.trace().unwrap();
+        assert_eq!(tracer.entries.as_ref().unwrap().len(), 5);
+
+        let mut csv = vec![];
+        tracer.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().nth(1), Some("0,0,in,12,12=1"));
+        assert_eq!(csv.lines().nth(3), Some("2,4,add,1 -1 12,12=0"));
+
+        let mut json = vec![];
+        tracer.write_json_lines(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert_eq!(json.lines().nth(2), Some("{\"cycle\":2,\"pc\":4,\"op\":\"add\",\"operands\":[1, -1, 12],\"writes\":[[12,0]]}"));
+    }
+}
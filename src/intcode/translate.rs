@@ -0,0 +1,288 @@
+//! Ahead-of-time translation of Intcode programs
+//!
+//! The interpreter decodes every instruction from memory each time it is executed. For
+//! programs that are run over and over, such as in day 2's noun/verb search, or that run for
+//! a long time, like day 9's BOOST, that decoding dominates. `translate` decodes every
+//! statically reachable instruction once, and `TranslatedProgram::execute` runs from that
+//! table instead.
+//!
+//! Translation assumes the program doesn't modify its own code. Should it try to write to an
+//! address holding translated code, or do anything else out of the ordinary (jumping
+//! somewhere that wasn't translated, running out of input, a bad address), execution carries
+//! on in an ordinary `Executor` from the same state. So results, faults included, are always
+//! the same as `execute_program`'s.
+
+use std::borrow::Cow;
+
+use crate::intcode::{ArgMode, Executor, IntcodeFault, StepOutcome, decode};
+use crate::intcode::disasm::reachable;
+use crate::intcode::memory::MAX_VEC_EXTENT;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Immediate(i64),
+    Absolute(i64),
+    Relative(i64),
+}
+
+/// A decoded instruction, ready to go
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Op {
+    opcode: i64,
+    args: [Operand; 3],
+    size: usize,
+}
+
+/// Decode the instruction at `pc`, if there is a valid one
+fn translate_at(mem: &[i64], pc: usize) -> Option<Op> {
+    let raw = decode(pc, |addr| mem.get(addr).copied().unwrap_or(0)).ok()?;
+    let mut args = [Operand::Immediate(0); 3];
+    for (i, (mode, arg)) in raw.modes.iter().zip(&raw.args).enumerate() {
+        args[i] = match mode {
+            ArgMode::Immediate => Operand::Immediate(*arg),
+            ArgMode::Absolute => Operand::Absolute(*arg),
+            ArgMode::Relative => Operand::Relative(*arg),
+        };
+    }
+    Some(Op{opcode: raw.opcode, args, size: raw.size()})
+}
+
+/// Final memory and output, as `execute_program` gives them
+pub type MemoryAndOutput = (Vec<i64>, Vec<i64>);
+
+pub struct TranslatedProgram {
+    program: Vec<i64>,
+    /// Decoded instruction for each address an instruction starts at
+    ops: Vec<Option<Op>>,
+    /// For each address that is part of an instruction, where that instruction starts
+    owner: Vec<Option<usize>>,
+}
+
+/// Translate every instruction reachable from address 0
+pub fn translate(program: &[i64]) -> TranslatedProgram {
+    let mut ops = vec![None; program.len()];
+    let mut owner = vec![None; program.len()];
+    for (pc, raw) in reachable(program) {
+        ops[pc] = translate_at(program, pc);
+        for o in &mut owner[pc..pc + raw.size()] {
+            *o = Some(pc);
+        }
+    }
+    TranslatedProgram{program: program.to_vec(), ops, owner}
+}
+
+/// Machine state while running translated code
+struct State {
+    mem: Vec<i64>,
+    pc: usize,
+    base_reg: i64,
+    next_input: usize,
+    output: Vec<i64>,
+    /// Instructions executed
+    steps: u64,
+}
+
+impl State {
+    fn value(&self, arg: Operand) -> Option<i64> {
+        let addr = match arg {
+            Operand::Immediate(x) => return Some(x),
+            Operand::Absolute(addr) => addr,
+            Operand::Relative(offset) => offset.wrapping_add(self.base_reg),
+        };
+        if addr < 0 {
+            return None;
+        }
+        Some(self.mem.get(addr as usize).copied().unwrap_or(0))
+    }
+
+    /// Callers check `addr` is below `MAX_VEC_EXTENT`, as the interpreter's flat memory would
+    fn store(&mut self, addr: usize, value: i64) {
+        if addr >= self.mem.len() {
+            self.mem.resize(addr + 1, 0);
+        }
+        self.mem[addr] = value;
+    }
+}
+
+impl TranslatedProgram {
+    /// Number of instructions translated
+    pub fn instruction_count(&self) -> usize {
+        self.ops.iter().filter(|op| op.is_some()).count()
+    }
+
+    /// Run with the given input, returning final memory and output like `execute_program`
+    pub fn execute(&self, input: &[i64]) -> Result<MemoryAndOutput, IntcodeFault> {
+        Ok(self.execute_patched(&[], input, None)?.expect("Nothing to run out of"))
+    }
+
+    /// Run with memory patched first, as (address, value) pairs. Any translated instructions
+    /// that are patched are translated again. Gives None if the program hasn't halted after
+    /// `max_steps` instructions.
+    pub fn execute_patched(&self, patches: &[(usize, i64)], input: &[i64], max_steps: Option<u64>) -> Result<Option<MemoryAndOutput>, IntcodeFault> {
+        let mut state = State{mem: self.program.clone(), pc: 0, base_reg: 0, next_input: 0, output: vec![], steps: 0};
+        let mut ops = Cow::Borrowed(&self.ops);
+        for (addr, value) in patches {
+            if *addr >= MAX_VEC_EXTENT {
+                return Err(IntcodeFault::BadAddress{pc: 0, instr: state.mem.first().copied().unwrap_or(0), addr: *addr as i64});
+            }
+            state.store(*addr, *value);
+            if let Some(start) = self.owner.get(*addr).copied().flatten() {
+                let old_size = self.ops[start].map(|op| op.size);
+                let op = translate_at(&state.mem, start).filter(|op| Some(op.size) == old_size);
+                ops.to_mut()[start] = op;
+            }
+        }
+
+        if self.run(&ops, &mut state, input, max_steps) {
+            return Ok(Some((state.mem, state.output)));
+        }
+
+        let steps_left = max_steps.map(|max| max - state.steps);
+        let mut m = Executor::new(state.mem);
+        m.pc = state.pc as u32;
+        m.base_reg = state.base_reg;
+        m.set_input(input[state.next_input..].to_vec());
+        m.set_step_budget(steps_left);
+        m.output = state.output;
+        if m.run()? == StepOutcome::BudgetExhausted {
+            return Ok(None);
+        }
+        Ok(Some((m.mem.into_vec().expect("Executor::new gives flat memory"), m.output)))
+    }
+
+    /// Run translated code until the program halts, returning true, or something comes up
+    /// that needs the interpreter, which includes reaching `max_steps`. In that case the
+    /// instruction at the PC hasn't been started.
+    fn run(&self, ops: &[Option<Op>], state: &mut State, input: &[i64], max_steps: Option<u64>) -> bool {
+        // Where an instruction can write: not a bad address, and not translated code
+        let dest = |arg: Operand, base_reg: i64| -> Option<usize> {
+            let addr = match arg {
+                Operand::Relative(offset) => offset.wrapping_add(base_reg),
+                Operand::Absolute(addr) | Operand::Immediate(addr) => addr,
+            };
+            if addr < 0 || addr as usize >= MAX_VEC_EXTENT || self.owner.get(addr as usize).is_some_and(|o| o.is_some()) {
+                return None;
+            }
+            Some(addr as usize)
+        };
+
+        loop {
+            if max_steps.is_some_and(|max| state.steps >= max) {
+                return false;
+            }
+            let op = match ops.get(state.pc) {
+                Some(Some(op)) => op,
+                _ => return false,
+            };
+            match op.opcode {
+                1 | 2 | 7 | 8 => {
+                    let (a, b) = match (state.value(op.args[0]), state.value(op.args[1])) {
+                        (Some(a), Some(b)) => (a, b),
+                        _ => return false,
+                    };
+                    let addr = match dest(op.args[2], state.base_reg) {
+                        Some(addr) => addr,
+                        None => return false,
+                    };
+                    let result = match op.opcode {
+                        1 => a.wrapping_add(b),
+                        2 => a.wrapping_mul(b),
+                        7 => (a < b) as i64,
+                        _ => (a == b) as i64,
+                    };
+                    state.store(addr, result);
+                },
+                3 => {
+                    let (addr, x) = match (dest(op.args[0], state.base_reg), input.get(state.next_input)) {
+                        (Some(addr), Some(x)) => (addr, *x),
+                        _ => return false,
+                    };
+                    state.next_input += 1;
+                    state.store(addr, x);
+                },
+                4 => match state.value(op.args[0]) {
+                    Some(x) => state.output.push(x),
+                    None => return false,
+                },
+                5 | 6 => {
+                    let (a, target) = match (state.value(op.args[0]), state.value(op.args[1])) {
+                        (Some(a), Some(target)) => (a, target),
+                        _ => return false,
+                    };
+                    if (a != 0) == (op.opcode == 5) {
+                        if target < 0 || target > u32::MAX as i64 {
+                            return false;
+                        }
+                        state.pc = target as usize;
+                        state.steps += 1;
+                        continue;
+                    }
+                },
+                9 => match state.value(op.args[0]) {
+                    Some(x) => state.base_reg = state.base_reg.wrapping_add(x),
+                    None => return false,
+                },
+                _ => return true,
+            }
+            state.pc += op.size;
+            state.steps += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::translate::*;
+    use crate::intcode::execute_program;
+    use crate::intcode::fixtures::day9;
+
+    #[test]
+    fn test_matches_interpreter() {
+        let program = day9();
+        let translated = translate(&program);
+        assert!(translated.instruction_count() > 100);
+        assert_eq!(translated.execute(&[1]), execute_program(&program, &vec![1]));
+        assert_eq!(translated.execute(&[]), execute_program(&program, &vec![]));
+
+        // Day 2 style patching of operands
+        let adder = vec![1, 0, 0, 0, 99];
+        let mut patched = adder.clone();
+        patched[1] = 4;
+        patched[2] = 4;
+        assert_eq!(translate(&adder).execute_patched(&[(1, 4), (2, 4)], &[], None), execute_program(&patched, &vec![]).map(Some));
+    }
+
+    #[test]
+    fn test_fallback() {
+        // Overwrites its own halt with an output instruction before reaching it
+        let program = vec![1101, 104, 0, 12, 1101, 0, 8, 13, 1106, 0, 12, 99, 99, 0, 99];
+        let translated = translate(&program);
+        assert_eq!(translated.execute(&[]), execute_program(&program, &vec![]));
+        assert_eq!(translated.execute(&[]).unwrap().1, vec![8]);
+
+        let faulty = vec![3, 5, 109, -10, 204, 0, 99];
+        assert_eq!(translate(&faulty).execute(&[1]), execute_program(&faulty, &vec![1]));
+        assert!(translate(&faulty).execute(&[]).is_err());
+
+        let huge = vec![1101, 1, 1, 1 << 40, 99];
+        assert_eq!(translate(&huge).execute(&[]), execute_program(&huge, &vec![]));
+        assert!(translate(&huge).execute(&[]).is_err());
+        assert!(translate(&[99]).execute_patched(&[(1 << 40, 1)], &[], None).is_err());
+    }
+
+    #[test]
+    fn test_max_steps() {
+        // Counts down from 3, then halts: 1 + 3 * 2 + 1 instructions, the halt included
+        let countdown = vec![1101, 3, 0, 13, 1001, 13, -1, 13, 1005, 13, 4, 99, 0, 0];
+        let translated = translate(&countdown);
+        assert_eq!(translated.execute_patched(&[], &[], Some(7)), Ok(None));
+        assert_eq!(translated.execute_patched(&[], &[], Some(8)), Ok(Some(execute_program(&countdown, &vec![]).unwrap())));
+
+        // Patched to loop forever
+        assert_eq!(translated.execute_patched(&[(11, 1105), (12, 1), (13, 0)], &[], Some(1000)), Ok(None));
+        // Needing the interpreter, which takes over what's left of the budget
+        let fallback = vec![1101, 104, 0, 12, 1101, 0, 8, 13, 1106, 0, 12, 99, 99, 0, 99];
+        assert_eq!(translate(&fallback).execute_patched(&[], &[], Some(4)), Ok(None));
+        assert!(translate(&fallback).execute_patched(&[], &[], Some(5)).unwrap().is_some());
+    }
+}
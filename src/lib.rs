@@ -14,7 +14,9 @@
 
 
 mod options;
+pub mod arcade;
 pub mod grid;
 pub mod intcode;
 pub mod io;
+pub mod robot;
 pub use options::StandardOptions;
\ No newline at end of file
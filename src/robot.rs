@@ -0,0 +1,264 @@
+//! Robots driven by Intcode programs
+//!
+//! Several puzzles have an Intcode program talking to a robot that moves around a grid. The
+//! only thing that changes between them is the protocol: what the program is told about the
+//! world, what its output means, and what the robot does about it. A `RobotProtocol` says
+//! that, and `Robot` does the rest: it keeps the robot's `Pose` and the world `Grid`, and
+//! shuttles values between the protocol and the program.
+//!
+//! Each step, the protocol `sense`s the world to make input for the program, the program
+//! runs until it has written `action_size` values, which are `decode`d into an action, and
+//! the protocol `act`s on it, moving the robot or changing the world.
+
+use crate::grid::{Direction, Grid, Location};
+use crate::intcode::{Executor, IntcodeFault, StopReason};
+
+/// Where a robot is and which way it's facing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pose {
+    pub loc: Location,
+    pub facing: Direction,
+}
+
+impl Pose {
+    pub fn new(loc: Location, facing: Direction) -> Pose {
+        Pose{loc, facing}
+    }
+
+    /// The location just in front
+    pub fn ahead(&self) -> Location {
+        self.loc.go_one(self.facing)
+    }
+
+    pub fn turn_left(&mut self) {
+        self.facing = self.facing.left();
+    }
+
+    pub fn turn_right(&mut self) {
+        self.facing = self.facing.right();
+    }
+
+    pub fn forward(&mut self) {
+        self.loc = self.ahead();
+    }
+}
+
+pub trait RobotProtocol {
+    /// What the world is made of
+    type Cell: Clone;
+    /// Something for the robot to do, as decoded from the program's output
+    type Action;
+
+    /// Number of output values that make up an action
+    fn action_size(&self) -> usize;
+
+    /// Input for the program, from what the robot can sense
+    fn sense(&mut self, world: &mut Grid<Self::Cell>, pose: &Pose) -> Vec<i64>;
+
+    fn decode(&mut self, output: &[i64]) -> Self::Action;
+
+    /// Carry out an action. Returns false if the robot should stop.
+    fn act(&mut self, world: &mut Grid<Self::Cell>, pose: &mut Pose, action: Self::Action) -> bool;
+}
+
+/// Why a robot stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// The program halted
+    Halted,
+    /// The protocol said to stop
+    Done,
+    /// The robot took as many steps as it was allowed, or the program ran out of budget
+    StepLimit,
+    /// The program wanted more input than the protocol gave it
+    NeedsInput,
+    /// The program reached a breakpoint set on the machine
+    Breakpoint(u32),
+    Fault(IntcodeFault),
+}
+
+#[derive(Clone)]
+pub struct Robot<P: RobotProtocol> {
+    pub machine: Executor,
+    pub protocol: P,
+    pub pose: Pose,
+    pub world: Grid<P::Cell>,
+    steps: usize,
+    limit: Option<usize>,
+}
+
+impl<P: RobotProtocol> Robot<P> {
+    pub fn new(machine: Executor, protocol: P, pose: Pose, world: Grid<P::Cell>) -> Robot<P> {
+        Robot{machine, protocol, pose, world, steps: 0, limit: None}
+    }
+
+    /// Number of steps taken so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Stop after this many steps
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// Sense, think and act once
+    pub fn step(&mut self) -> Result<(), Stop> {
+        if self.limit.is_some_and(|limit| self.steps >= limit) {
+            return Err(Stop::StepLimit);
+        }
+        for x in self.protocol.sense(&mut self.world, &self.pose) {
+            self.machine.push_input(x);
+        }
+
+        let size = self.protocol.action_size();
+        let mut outputs = self.machine.outputs();
+        let output: Vec<i64> = outputs.by_ref().take(size).collect();
+        if output.len() < size {
+            return Err(match outputs.stop_reason().expect("Outputs ended without a reason") {
+                Ok(StopReason::Halted) => Stop::Halted,
+                Ok(StopReason::NeedsInput) => Stop::NeedsInput,
+                Ok(StopReason::BudgetExhausted) => Stop::StepLimit,
+                Ok(StopReason::Breakpoint(pc)) => Stop::Breakpoint(pc),
+                Ok(StopReason::Output(_)) => unreachable!("Outputs only end for other reasons"),
+                Err(fault) => Stop::Fault(fault),
+            });
+        }
+        // The values are in hand, so don't let them pile up in the machine
+        self.machine.output.clear();
+
+        let action = self.protocol.decode(&output);
+        self.steps += 1;
+        if self.protocol.act(&mut self.world, &mut self.pose, action) {
+            Ok(())
+        } else {
+            Err(Stop::Done)
+        }
+    }
+
+    /// Step until something stops the robot
+    pub fn run(&mut self) -> Stop {
+        self.run_with(|_| ())
+    }
+
+    /// Step until something stops the robot, letting `observer` look on after every step
+    pub fn run_with<F: FnMut(&Robot<P>)>(&mut self, mut observer: F) -> Stop {
+        loop {
+            match self.step() {
+                Ok(()) => observer(self),
+                Err(stop) => return stop,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::grid::xy;
+    use crate::intcode::asm::assemble;
+    use crate::robot::*;
+
+    /// Langton's ant: on white turn right, on black turn left, and flip the colour
+    #[derive(Clone)]
+    struct Ant;
+
+    impl RobotProtocol for Ant {
+        type Cell = bool;
+        type Action = (bool, bool);
+
+        fn action_size(&self) -> usize {
+            2
+        }
+
+        fn sense(&mut self, world: &mut Grid<bool>, pose: &Pose) -> Vec<i64> {
+            vec![world.get(&pose.loc).unwrap_or(false) as i64]
+        }
+
+        fn decode(&mut self, output: &[i64]) -> (bool, bool) {
+            (output[0] == 1, output[1] == 1)
+        }
+
+        fn act(&mut self, world: &mut Grid<bool>, pose: &mut Pose, (black, right): (bool, bool)) -> bool {
+            world.set(&pose.loc, Some(black));
+            if right {
+                pose.turn_right();
+            } else {
+                pose.turn_left();
+            }
+            pose.forward();
+            true
+        }
+    }
+
+    fn ant_brain() -> Vec<i64> {
+        assemble("
+            loop:   in    c
+                    jt    c, #black
+                    out   #1
+                    out   #1
+                    jt    #1, #loop
+            black:  out   #0
+                    out   #0
+                    jt    #1, #loop
+            c:      data  0
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_ant() {
+        let mut robot = Robot::new(Executor::new(ant_brain()), Ant, Pose::new(xy(0, 0), Direction::North), Grid::empty());
+        robot.set_step_limit(Some(500));
+        let mut visited = HashSet::new();
+        assert_eq!(robot.run_with(|r| { visited.insert(r.pose.loc); }), Stop::StepLimit);
+        assert_eq!(robot.steps(), 500);
+
+        // Same again, by hand
+        let mut black = HashSet::new();
+        let mut pose = Pose::new(xy(0, 0), Direction::North);
+        for _ in 0..500 {
+            if black.remove(&pose.loc) {
+                pose.turn_left();
+            } else {
+                black.insert(pose.loc);
+                pose.turn_right();
+            }
+            pose.forward();
+        }
+        assert_eq!(robot.pose, pose);
+        for loc in &visited {
+            assert_eq!(robot.world.peek(loc).unwrap_or(false), black.contains(loc));
+        }
+        assert!(robot.machine.output.is_empty());
+    }
+
+    #[test]
+    fn test_halt() {
+        let brain = assemble("
+                    in    c
+                    out   #1
+                    out   #0
+                    in    c
+                    out   #1
+                    halt
+            c:      data  0
+        ").unwrap();
+        let mut robot = Robot::new(Executor::new(brain), Ant, Pose::new(xy(0, 0), Direction::East), Grid::empty());
+        assert_eq!(robot.run(), Stop::Halted);
+        assert_eq!((robot.steps(), robot.pose), (1, Pose::new(xy(0, -1), Direction::North)));
+
+        // Wants two inputs each step
+        let greedy = assemble("
+                    in    c
+                    in    c
+                    out   #1
+                    out   #1
+                    halt
+            c:      data  0
+        ").unwrap();
+        let mut robot = Robot::new(Executor::new(greedy), Ant, Pose::new(xy(0, 0), Direction::East), Grid::empty());
+        assert_eq!(robot.run(), Stop::NeedsInput);
+        assert_eq!(robot.steps(), 0);
+    }
+}